@@ -0,0 +1,127 @@
+//! Benchmarks of the `std` implementation against the one in version 1.2.0, to measure the cost
+//! of the depth and owner tracking added since.
+//!
+//! Run with `cargo bench --features std`.
+
+use std::sync::Barrier;
+use std::thread;
+use std::time::{Duration, Instant};
+
+/// The `std` implementation as of version 1.2.0: a global `std::sync::Mutex` whose
+/// guard is stored in a static while the CS is held, with a thread-local flag for reentrancy.
+mod mutex {
+    use std::cell::Cell;
+    use std::mem::MaybeUninit;
+    use std::ptr::addr_of_mut;
+    use std::sync::{Mutex, MutexGuard};
+
+    static GLOBAL_MUTEX: Mutex<()> = Mutex::new(());
+    static mut GLOBAL_GUARD: MaybeUninit<MutexGuard<'static, ()>> = MaybeUninit::uninit();
+
+    std::thread_local!(static IS_LOCKED: Cell<bool> = const { Cell::new(false) });
+
+    // Not inlined, like the implementation called through `set_impl!`.
+    #[inline(never)]
+    unsafe fn acquire() -> bool {
+        IS_LOCKED.with(|l| {
+            if l.get() {
+                return true;
+            }
+            l.set(true);
+            let guard = match GLOBAL_MUTEX.lock() {
+                Ok(guard) => guard,
+                Err(err) => err.into_inner(),
+            };
+            (*addr_of_mut!(GLOBAL_GUARD)).write(guard);
+            false
+        })
+    }
+
+    #[inline(never)]
+    unsafe fn release(nested_cs: bool) {
+        if !nested_cs {
+            #[allow(let_underscore_lock)]
+            let _ = (*addr_of_mut!(GLOBAL_GUARD)).assume_init_read();
+            IS_LOCKED.with(|l| l.set(false));
+        }
+    }
+
+    /// Like `critical_section::with` as of version 1.2.0.
+    pub fn with<R>(f: impl FnOnce() -> R) -> R {
+        struct Guard {
+            nested_cs: bool,
+        }
+
+        impl Drop for Guard {
+            fn drop(&mut self) {
+                unsafe { release(self.nested_cs) }
+            }
+        }
+
+        let _guard = Guard {
+            nested_cs: unsafe { acquire() },
+        };
+        f()
+    }
+}
+
+const ITERATIONS: u32 = 1_000_000;
+const THREADS: u32 = 4;
+
+/// Returns the average time in nanoseconds of an iteration of `f`, run `ITERATIONS` times in each
+/// of `threads` threads at once.
+fn measure(threads: u32, f: fn()) -> f64 {
+    let barrier = Barrier::new(threads as usize);
+    let total: Duration = thread::scope(|s| {
+        let handles: Vec<_> = (0..threads)
+            .map(|_| {
+                s.spawn(|| {
+                    barrier.wait();
+                    let start = Instant::now();
+                    for _ in 0..ITERATIONS {
+                        f();
+                    }
+                    start.elapsed()
+                })
+            })
+            .collect();
+        handles.into_iter().map(|h| h.join().unwrap()).sum()
+    });
+    total.as_secs_f64() * 1e9 / f64::from(threads * ITERATIONS)
+}
+
+fn bench(name: &str, threads: u32, old: fn(), new: fn()) {
+    // Warm up.
+    measure(threads, old);
+    measure(threads, new);
+    let old = measure(threads, old);
+    let new = measure(threads, new);
+    println!(
+        "{:<12} 1.2.0: {:>7.1} ns   current: {:>7.1} ns   ratio: {:.2}x",
+        name,
+        old,
+        new,
+        old / new
+    );
+}
+
+fn main() {
+    bench(
+        "uncontended",
+        1,
+        || mutex::with(|| ()),
+        || critical_section::with(|_| ()),
+    );
+    bench(
+        "nested",
+        1,
+        || mutex::with(|| mutex::with(|| ())),
+        || critical_section::with(|_| critical_section::with(|_| ())),
+    );
+    bench(
+        "contended",
+        THREADS,
+        || mutex::with(|| ()),
+        || critical_section::with(|_| ()),
+    );
+}
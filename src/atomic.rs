@@ -0,0 +1,429 @@
+//! Atomic types emulated with critical sections.
+//!
+//! These types mirror the API of the ones in [`core::sync::atomic`], so they can be used as drop-in
+//! replacements on targets that lack some atomic operations, such as compare-and-swap or 64-bit
+//! atomics. Every operation runs inside a critical section.
+//!
+//! The memory ordering arguments are accepted for API compatibility, but ignored. Operations
+//! are always at least as strong as [`Ordering::SeqCst`], since critical sections are totally
+//! ordered.
+//!
+//! Note that these types are only atomic with respect to each other, and to other code using
+//! critical sections. Mixing them with native atomic operations on the same memory is not
+//! supported.
+//!
+//! # Example
+//!
+//! ```
+//! use critical_section::atomic::{AtomicU64, Ordering};
+//!
+//! static TICKS: AtomicU64 = AtomicU64::new(0);
+//!
+//! fn on_tick() {
+//!     TICKS.fetch_add(1, Ordering::Relaxed);
+//! }
+//! # #[cfg(not(feature = "std"))] // needed for `cargo test --features std`
+//! # mod no_std {
+//! #     struct MyCriticalSection;
+//! #     critical_section::set_impl!(MyCriticalSection);
+//! #     unsafe impl critical_section::Impl for MyCriticalSection {
+//! #         unsafe fn acquire() -> () {}
+//! #         unsafe fn release(token: ()) {}
+//! #     }
+//! # }
+//! # fn main() {
+//! #     on_tick();
+//! #     assert_eq!(TICKS.load(Ordering::Relaxed), 1);
+//! # }
+//! ```
+
+use core::cell::Cell;
+use core::fmt;
+use core::ptr;
+
+pub use core::sync::atomic::Ordering;
+
+use crate::Mutex;
+
+macro_rules! common_methods {
+    ($atomic:ident, $t:ty) => {
+        /// Stores a value, returning the previous value.
+        #[inline]
+        pub fn swap(&self, val: $t, _order: Ordering) -> $t {
+            self.v.replace_in_cs(val)
+        }
+
+        /// Loads the value.
+        #[inline]
+        pub fn load(&self, _order: Ordering) -> $t {
+            self.v.get_in_cs()
+        }
+
+        /// Stores a value.
+        #[inline]
+        pub fn store(&self, val: $t, _order: Ordering) {
+            self.v.set_in_cs(val)
+        }
+
+        /// Stores `new` if the current value is `current`.
+        ///
+        /// Returns `Ok(previous_value)` if the value was updated, `Err(previous_value)` otherwise.
+        #[inline]
+        pub fn compare_exchange(
+            &self,
+            current: $t,
+            new: $t,
+            _success: Ordering,
+            _failure: Ordering,
+        ) -> Result<$t, $t> {
+            self.v
+                .fetch_update_in_cs(|prev| if prev == current { Some(new) } else { None })
+        }
+
+        /// Stores `new` if the current value is `current`.
+        ///
+        /// Unlike the native version, this never fails spuriously. It is provided for API
+        /// compatibility and behaves exactly like
+        #[doc = concat!("[`", stringify!($atomic), "::compare_exchange`].")]
+        #[inline]
+        pub fn compare_exchange_weak(
+            &self,
+            current: $t,
+            new: $t,
+            success: Ordering,
+            failure: Ordering,
+        ) -> Result<$t, $t> {
+            self.compare_exchange(current, new, success, failure)
+        }
+
+        /// Fetches the value, and applies `f` to it, storing the new value if `f` returns `Some`.
+        ///
+        /// Returns `Ok(previous_value)` if `f` returned `Some`, `Err(previous_value)` otherwise.
+        /// Unlike the native version, `f` is called exactly once, inside a critical section.
+        #[inline]
+        pub fn fetch_update<F>(
+            &self,
+            _set_order: Ordering,
+            _fetch_order: Ordering,
+            f: F,
+        ) -> Result<$t, $t>
+        where
+            F: FnMut($t) -> Option<$t>,
+        {
+            self.v.fetch_update_in_cs(f)
+        }
+
+        /// Returns a mutable reference to the value.
+        ///
+        /// This does not require a critical section since it takes `&mut self`.
+        #[inline]
+        pub fn get_mut(&mut self) -> &mut $t {
+            self.v.get_mut().get_mut()
+        }
+
+        /// Consumes the atomic and returns the contained value.
+        #[inline]
+        pub fn into_inner(self) -> $t {
+            self.v.into_inner().into_inner()
+        }
+    };
+}
+
+/// Applies `op` to the value, returning the previous value.
+#[inline(always)]
+fn fetch_op<T: Copy>(v: &Mutex<Cell<T>>, op: impl FnOnce(T) -> T) -> T {
+    match v.fetch_update_in_cs(|prev| Some(op(prev))) {
+        Ok(prev) | Err(prev) => prev,
+    }
+}
+
+macro_rules! atomic_int {
+    ($atomic:ident, $t:ty) => {
+        #[doc = concat!("Emulated version of [`core::sync::atomic::", stringify!($atomic), "`].")]
+        #[repr(transparent)]
+        pub struct $atomic {
+            v: Mutex<Cell<$t>>,
+        }
+
+        impl $atomic {
+            /// Creates a new atomic.
+            #[inline]
+            pub const fn new(v: $t) -> Self {
+                $atomic {
+                    v: Mutex::new(Cell::new(v)),
+                }
+            }
+
+            common_methods!($atomic, $t);
+
+            /// Adds to the current value, wrapping around on overflow, and returns the previous value.
+            #[inline]
+            pub fn fetch_add(&self, val: $t, _order: Ordering) -> $t {
+                fetch_op(&self.v, |prev| prev.wrapping_add(val))
+            }
+
+            /// Subtracts from the current value, wrapping around on overflow, and returns the
+            /// previous value.
+            #[inline]
+            pub fn fetch_sub(&self, val: $t, _order: Ordering) -> $t {
+                fetch_op(&self.v, |prev| prev.wrapping_sub(val))
+            }
+
+            /// Bitwise "and" with the current value, returning the previous value.
+            #[inline]
+            pub fn fetch_and(&self, val: $t, _order: Ordering) -> $t {
+                fetch_op(&self.v, |prev| prev & val)
+            }
+
+            /// Bitwise "nand" with the current value, returning the previous value.
+            #[inline]
+            pub fn fetch_nand(&self, val: $t, _order: Ordering) -> $t {
+                fetch_op(&self.v, |prev| !(prev & val))
+            }
+
+            /// Bitwise "or" with the current value, returning the previous value.
+            #[inline]
+            pub fn fetch_or(&self, val: $t, _order: Ordering) -> $t {
+                fetch_op(&self.v, |prev| prev | val)
+            }
+
+            /// Bitwise "xor" with the current value, returning the previous value.
+            #[inline]
+            pub fn fetch_xor(&self, val: $t, _order: Ordering) -> $t {
+                fetch_op(&self.v, |prev| prev ^ val)
+            }
+
+            /// Maximum with the current value, returning the previous value.
+            #[inline]
+            pub fn fetch_max(&self, val: $t, _order: Ordering) -> $t {
+                fetch_op(&self.v, |prev| prev.max(val))
+            }
+
+            /// Minimum with the current value, returning the previous value.
+            #[inline]
+            pub fn fetch_min(&self, val: $t, _order: Ordering) -> $t {
+                fetch_op(&self.v, |prev| prev.min(val))
+            }
+        }
+
+        impl Default for $atomic {
+            #[inline]
+            fn default() -> Self {
+                Self::new(Default::default())
+            }
+        }
+
+        impl From<$t> for $atomic {
+            #[inline]
+            fn from(v: $t) -> Self {
+                Self::new(v)
+            }
+        }
+
+        impl fmt::Debug for $atomic {
+            fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+                fmt::Debug::fmt(&self.load(Ordering::SeqCst), f)
+            }
+        }
+    };
+}
+
+atomic_int!(AtomicI8, i8);
+atomic_int!(AtomicU8, u8);
+atomic_int!(AtomicI16, i16);
+atomic_int!(AtomicU16, u16);
+atomic_int!(AtomicI32, i32);
+atomic_int!(AtomicU32, u32);
+atomic_int!(AtomicI64, i64);
+atomic_int!(AtomicU64, u64);
+atomic_int!(AtomicIsize, isize);
+atomic_int!(AtomicUsize, usize);
+
+/// Emulated version of [`core::sync::atomic::AtomicBool`].
+#[repr(transparent)]
+pub struct AtomicBool {
+    v: Mutex<Cell<bool>>,
+}
+
+impl AtomicBool {
+    /// Creates a new atomic.
+    #[inline]
+    pub const fn new(v: bool) -> Self {
+        AtomicBool {
+            v: Mutex::new(Cell::new(v)),
+        }
+    }
+
+    common_methods!(AtomicBool, bool);
+
+    /// Logical "and" with the current value, returning the previous value.
+    #[inline]
+    pub fn fetch_and(&self, val: bool, _order: Ordering) -> bool {
+        fetch_op(&self.v, |prev| prev & val)
+    }
+
+    /// Logical "nand" with the current value, returning the previous value.
+    #[inline]
+    pub fn fetch_nand(&self, val: bool, _order: Ordering) -> bool {
+        fetch_op(&self.v, |prev| !(prev & val))
+    }
+
+    /// Logical "or" with the current value, returning the previous value.
+    #[inline]
+    pub fn fetch_or(&self, val: bool, _order: Ordering) -> bool {
+        fetch_op(&self.v, |prev| prev | val)
+    }
+
+    /// Logical "xor" with the current value, returning the previous value.
+    #[inline]
+    pub fn fetch_xor(&self, val: bool, _order: Ordering) -> bool {
+        fetch_op(&self.v, |prev| prev ^ val)
+    }
+
+    /// Logical "not" of the current value, returning the previous value.
+    #[inline]
+    pub fn fetch_not(&self, _order: Ordering) -> bool {
+        fetch_op(&self.v, |prev| !prev)
+    }
+}
+
+impl Default for AtomicBool {
+    #[inline]
+    fn default() -> Self {
+        Self::new(false)
+    }
+}
+
+impl From<bool> for AtomicBool {
+    #[inline]
+    fn from(v: bool) -> Self {
+        Self::new(v)
+    }
+}
+
+impl fmt::Debug for AtomicBool {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt::Debug::fmt(&self.load(Ordering::SeqCst), f)
+    }
+}
+
+/// Emulated version of [`core::sync::atomic::AtomicPtr`].
+#[repr(transparent)]
+pub struct AtomicPtr<T> {
+    v: Mutex<Cell<*mut T>>,
+}
+
+impl<T> AtomicPtr<T> {
+    /// Creates a new atomic.
+    #[inline]
+    pub const fn new(p: *mut T) -> Self {
+        AtomicPtr {
+            v: Mutex::new(Cell::new(p)),
+        }
+    }
+
+    common_methods!(AtomicPtr, *mut T);
+}
+
+impl<T> Default for AtomicPtr<T> {
+    #[inline]
+    fn default() -> Self {
+        Self::new(ptr::null_mut())
+    }
+}
+
+impl<T> From<*mut T> for AtomicPtr<T> {
+    #[inline]
+    fn from(p: *mut T) -> Self {
+        Self::new(p)
+    }
+}
+
+impl<T> fmt::Debug for AtomicPtr<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt::Debug::fmt(&self.load(Ordering::SeqCst), f)
+    }
+}
+
+// NOTE Like `core::sync::atomic::AtomicPtr`, this only shares the pointer itself, not the pointee.
+unsafe impl<T> Send for AtomicPtr<T> {}
+unsafe impl<T> Sync for AtomicPtr<T> {}
+
+#[cfg(all(test, feature = "std"))]
+mod tests {
+    use std::sync::Arc;
+    use std::thread;
+
+    use super::{AtomicBool, AtomicI8, AtomicPtr, AtomicU64, Ordering::SeqCst};
+
+    #[test]
+    fn int() {
+        let a = AtomicI8::new(i8::MAX);
+        assert_eq!(a.fetch_add(1, SeqCst), i8::MAX);
+        assert_eq!(a.fetch_sub(1, SeqCst), i8::MIN);
+        assert_eq!(a.swap(0b0110, SeqCst), i8::MAX);
+        assert_eq!(a.fetch_and(0b0011, SeqCst), 0b0110);
+        assert_eq!(a.fetch_or(0b0100, SeqCst), 0b0010);
+        assert_eq!(a.fetch_xor(0b0011, SeqCst), 0b0110);
+        assert_eq!(a.fetch_nand(0b0101, SeqCst), 0b0101);
+        assert_eq!(a.fetch_max(3, SeqCst), !0b0101);
+        assert_eq!(a.fetch_min(-5, SeqCst), 3);
+        assert_eq!(a.compare_exchange(0, 1, SeqCst, SeqCst), Err(-5));
+        assert_eq!(a.compare_exchange_weak(-5, 1, SeqCst, SeqCst), Ok(-5));
+        assert_eq!(
+            a.fetch_update(SeqCst, SeqCst, |v| v.checked_add(i8::MAX)),
+            Err(1)
+        );
+        assert_eq!(a.fetch_update(SeqCst, SeqCst, |v| v.checked_mul(10)), Ok(1));
+        a.store(42, SeqCst);
+        assert_eq!(a.load(SeqCst), 42);
+        assert_eq!(a.into_inner(), 42);
+    }
+
+    #[test]
+    fn int_contended() {
+        let a = Arc::new(AtomicU64::new(0));
+        let threads: Vec<_> = (0..4)
+            .map(|_| {
+                let a = a.clone();
+                thread::spawn(move || {
+                    for _ in 0..1000 {
+                        a.fetch_add(1 << 32, SeqCst);
+                    }
+                })
+            })
+            .collect();
+        for t in threads {
+            t.join().unwrap();
+        }
+        assert_eq!(a.load(SeqCst), 4000 << 32);
+    }
+
+    #[test]
+    fn bool() {
+        let mut a = AtomicBool::new(false);
+        assert!(!a.fetch_not(SeqCst));
+        assert!(a.fetch_and(false, SeqCst));
+        assert!(!a.fetch_or(true, SeqCst));
+        assert!(a.fetch_xor(true, SeqCst));
+        assert!(!a.fetch_nand(true, SeqCst));
+        assert_eq!(a.compare_exchange(true, false, SeqCst, SeqCst), Ok(true));
+        *a.get_mut() = true;
+        assert!(a.load(SeqCst));
+    }
+
+    #[test]
+    fn ptr() {
+        let mut x = 1;
+        let mut y = 2;
+        let a = AtomicPtr::<i32>::default();
+        assert!(a.load(SeqCst).is_null());
+        a.store(&mut x, SeqCst);
+        assert_eq!(
+            a.compare_exchange(&mut y, &mut y, SeqCst, SeqCst),
+            Err(&mut x as *mut _)
+        );
+        assert_eq!(a.swap(&mut y, SeqCst), &mut x as *mut _);
+        assert_eq!(unsafe { *a.load(SeqCst) }, 2);
+    }
+}
@@ -0,0 +1,293 @@
+//! Conformance test suite for critical section implementations.
+//!
+//! The checks in this module exercise an [`Impl`] directly, and panic with a description of the
+//! problem if it doesn't honour the contract specified in [`acquire`](crate::acquire) and
+//! [`release`](crate::release). They are meant to be called from host tests, either on the
+//! implementation itself or on a mock of the hardware it runs on.
+//!
+//! Some checks spawn threads that contend for the critical section, so the implementation must
+//! provide mutual exclusion between threads. Host mocks of single-core implementations usually
+//! need to emulate the interrupt controller with a lock to pass them.
+//!
+//! This module requires the `conformance` Cargo feature, and a target supporting `std`.
+//!
+//! # Example
+//!
+//! ```
+//! use std::cell::Cell;
+//! use std::sync::{Mutex, MutexGuard};
+//!
+//! use critical_section::RawRestoreState;
+//!
+//! // Host mock of a non-reentrant implementation, using a global lock.
+//! static LOCK: Mutex<()> = Mutex::new(());
+//! static mut GUARD: Option<MutexGuard<'static, ()>> = None;
+//! std::thread_local!(static DEPTH: Cell<usize> = Cell::new(0));
+//!
+//! struct MyCriticalSection;
+//! unsafe impl critical_section::Impl for MyCriticalSection {
+//!     unsafe fn acquire() -> RawRestoreState {
+//!         if DEPTH.with(|d| d.replace(d.get() + 1)) == 0 {
+//!             let guard = LOCK.lock().unwrap_or_else(|e| e.into_inner());
+//!             *std::ptr::addr_of_mut!(GUARD) = Some(guard);
+//!         }
+//!         RawRestoreState::default()
+//!     }
+//!
+//!     unsafe fn release(_restore_state: RawRestoreState) {
+//!         if DEPTH.with(|d| d.replace(d.get() - 1)) == 1 {
+//!             // Take the guard out before unlocking, another thread may store its own.
+//!             let guard = (*std::ptr::addr_of_mut!(GUARD)).take();
+//!             drop(guard);
+//!         }
+//!     }
+//!
+//!     fn depth() -> Option<usize> {
+//!         Some(DEPTH.with(|d| d.get()))
+//!     }
+//! }
+//!
+//! // Usually in a `#[test]` function.
+//! critical_section::conformance::run_all::<MyCriticalSection>();
+//! ```
+
+// `RawRestoreState` is `()` without any `restore-state-*` feature.
+#![allow(clippy::let_unit_value, clippy::unit_arg, clippy::unit_cmp)]
+
+extern crate std;
+
+use core::cell::UnsafeCell;
+use core::marker::PhantomData;
+use std::panic::{self, AssertUnwindSafe};
+use std::sync::{mpsc, Arc};
+use std::thread;
+use std::time::Duration;
+use std::vec::Vec;
+
+use crate::Impl;
+
+/// How long checks wait for another thread to acquire the critical section before failing.
+const TIMEOUT: Duration = Duration::from_secs(10);
+
+/// How long checks give another thread to wrongly acquire the critical section held by the
+/// current one.
+const HELD_TIMEOUT: Duration = Duration::from_millis(100);
+
+/// Runs all the checks in this module.
+///
+/// # Panics
+///
+/// This function panics if `I` fails any of the checks.
+pub fn run_all<I: Impl + 'static>() {
+    check_nesting::<I>();
+    check_restore_state::<I>();
+    check_mutual_exclusion::<I>();
+    check_panic_safety::<I>();
+}
+
+/// Checks that nested critical sections can be acquired, and that the critical section is fully
+/// released once all of them are.
+///
+/// If `I` reports [`Impl::is_acquired`] or [`Impl::depth`], their values are checked too.
+///
+/// # Panics
+///
+/// This function panics if `I` fails the check.
+pub fn check_nesting<I: Impl + 'static>() {
+    const DEPTH: usize = 4;
+
+    check_state::<I>(0, "before acquiring");
+    unsafe {
+        let mut states = Vec::new();
+        for depth in 1..=DEPTH {
+            states.push(I::acquire());
+            check_state::<I>(depth, "after acquiring");
+        }
+        for depth in (0..DEPTH).rev() {
+            I::release(states.pop().unwrap());
+            check_state::<I>(depth, "after releasing");
+        }
+    }
+    check_acquirable_from_other_thread::<I>("after releasing nested critical sections");
+}
+
+/// Checks that releasing a critical section restores the state from before it was acquired.
+///
+/// Releasing a nested critical section must leave the outer one acquired, and releasing the
+/// outermost one must make the critical section acquirable again, by this thread and others.
+///
+/// If `I` reports [`Impl::is_acquired`] or [`Impl::depth`], their values are checked too.
+///
+/// # Panics
+///
+/// This function panics if `I` fails the check.
+pub fn check_restore_state<I: Impl + 'static>() {
+    unsafe {
+        let outer = I::acquire();
+        let inner = I::acquire();
+        I::release(inner);
+        check_state::<I>(1, "after releasing");
+        let waiting = check_not_acquirable_from_other_thread::<I>("after releasing a nested one");
+        let inner = I::acquire();
+        check_state::<I>(2, "after acquiring");
+        I::release(inner);
+        I::release(outer);
+        assert!(
+            waiting.recv_timeout(TIMEOUT).is_ok(),
+            "critical section can't be acquired by a waiting thread after releasing"
+        );
+    }
+    check_state::<I>(0, "after releasing");
+    check_acquirable_from_other_thread::<I>("after releasing");
+
+    unsafe {
+        let state = I::acquire();
+        check_state::<I>(1, "after acquiring");
+        I::release(state);
+    }
+    check_state::<I>(0, "after releasing");
+    check_acquirable_from_other_thread::<I>("after releasing again");
+}
+
+/// Checks that the critical section provides mutual exclusion between threads, including when
+/// it's acquired in a nested way.
+///
+/// # Panics
+///
+/// This function panics if `I` fails the check.
+pub fn check_mutual_exclusion<I: Impl + 'static>() {
+    const THREADS: usize = 4;
+    const ITERATIONS: usize = 200;
+
+    struct Counter(UnsafeCell<usize>);
+    // SAFETY: only accessed while holding the critical section.
+    unsafe impl Sync for Counter {}
+
+    let counter = Arc::new(Counter(UnsafeCell::new(0)));
+    let threads: Vec<_> = (0..THREADS)
+        .map(|_| {
+            let counter = counter.clone();
+            thread::spawn(move || {
+                for i in 0..ITERATIONS {
+                    unsafe {
+                        let outer = I::acquire();
+                        let inner = (i % 2 == 0).then(|| I::acquire());
+                        // Non-atomic read-modify-write, only correct with mutual exclusion.
+                        let value = *counter.0.get();
+                        thread::yield_now();
+                        *counter.0.get() = value + 1;
+                        if let Some(inner) = inner {
+                            I::release(inner);
+                        }
+                        I::release(outer);
+                    }
+                }
+            })
+        })
+        .collect();
+    for t in threads {
+        t.join().unwrap();
+    }
+
+    let count = unsafe { *counter.0.get() };
+    assert_eq!(
+        count,
+        THREADS * ITERATIONS,
+        "critical section doesn't provide mutual exclusion between threads"
+    );
+}
+
+/// Checks that the critical section is released when a closure running in it panics, the same
+/// way [`with`](crate::with) does.
+///
+/// # Panics
+///
+/// This function panics if `I` fails the check.
+pub fn check_panic_safety<I: Impl + 'static>() {
+    struct Guard<I: Impl>(crate::RawRestoreState, PhantomData<I>);
+
+    impl<I: Impl> Drop for Guard<I> {
+        fn drop(&mut self) {
+            unsafe { I::release(self.0) }
+        }
+    }
+
+    let res = panic::catch_unwind(AssertUnwindSafe(|| {
+        let _outer = Guard::<I>(unsafe { I::acquire() }, PhantomData);
+        let _inner = Guard::<I>(unsafe { I::acquire() }, PhantomData);
+        panic!("conformance check panic, this is expected");
+    }));
+    assert!(res.is_err());
+
+    check_state::<I>(0, "after panicking");
+    check_acquirable_from_other_thread::<I>("after panicking");
+
+    // Panicking in another thread must not poison the critical section either.
+    let _ = thread::spawn(|| {
+        let _guard = Guard::<I>(unsafe { I::acquire() }, PhantomData);
+        panic!("conformance check panic, this is expected");
+    })
+    .join();
+    unsafe {
+        let state = I::acquire();
+        I::release(state);
+    }
+}
+
+/// Checks the values of [`Impl::is_acquired`] and [`Impl::depth`], if `I` reports them.
+#[track_caller]
+fn check_state<I: Impl>(depth: usize, when: &str) {
+    if let Some(acquired) = I::is_acquired() {
+        assert_eq!(
+            acquired,
+            depth > 0,
+            "`is_acquired` returned the wrong value {} {} critical section(s)",
+            when,
+            depth
+        );
+    }
+    if let Some(actual) = I::depth() {
+        assert_eq!(
+            actual, depth,
+            "`depth` returned the wrong value {} {} critical section(s)",
+            when, depth
+        );
+    }
+}
+
+/// Checks that another thread can't acquire the critical section held by the current thread.
+///
+/// Returns a receiver notified once the other thread acquires it, after the current thread
+/// releases it.
+#[track_caller]
+fn check_not_acquirable_from_other_thread<I: Impl + 'static>(when: &str) -> mpsc::Receiver<()> {
+    let (tx, rx) = mpsc::channel();
+    thread::spawn(move || unsafe {
+        let state = I::acquire();
+        I::release(state);
+        let _ = tx.send(());
+    });
+    assert!(
+        rx.recv_timeout(HELD_TIMEOUT).is_err(),
+        "critical section acquired by another thread while held by the current one {}",
+        when
+    );
+    rx
+}
+
+/// Checks that the critical section is not held by the current thread anymore.
+#[track_caller]
+fn check_acquirable_from_other_thread<I: Impl + 'static>(when: &str) {
+    let (tx, rx) = mpsc::channel();
+    // The thread is leaked if it can't acquire the critical section.
+    thread::spawn(move || unsafe {
+        let state = I::acquire();
+        I::release(state);
+        let _ = tx.send(());
+    });
+    assert!(
+        rx.recv_timeout(TIMEOUT).is_ok(),
+        "critical section can't be acquired by another thread {}",
+        when
+    );
+}
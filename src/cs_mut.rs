@@ -0,0 +1,177 @@
+use super::{CriticalSection, CriticalSectionGuard};
+use core::cell::Cell;
+use core::marker::PhantomData;
+
+/// Unique critical section token.
+///
+/// Unlike [`CriticalSection`], this token can't be copied, and only one of them can exist at a
+/// time. It is the only way to access the data of a [`MutexMut`](crate::MutexMut), so holding
+/// `&mut CriticalSectionMut` proves that nothing else can access that data, which allows
+/// [`MutexMut::borrow_mut`](crate::MutexMut::borrow_mut) to hand out `&mut T` without any runtime
+/// borrow tracking.
+///
+/// It is obtained from [`with_mut`].
+#[derive(Debug)]
+pub struct CriticalSectionMut<'cs> {
+    _private: PhantomData<&'cs ()>,
+
+    // Prevent CriticalSectionMut from being Send or Sync, for the same reasons as CriticalSection.
+    _not_send_sync: PhantomData<*mut ()>,
+}
+
+impl<'cs> CriticalSectionMut<'cs> {
+    /// Returns a shared critical section token, to access [`Mutex`](crate::Mutex)es.
+    #[inline(always)]
+    pub fn token(&self) -> CriticalSection<'_> {
+        // SAFETY: the critical section is held for as long as `self` exists.
+        unsafe { CriticalSection::new() }
+    }
+}
+
+// Whether a `CriticalSectionMut` currently exists.
+struct Exclusive(Cell<bool>);
+
+// SAFETY: `EXCLUSIVE` is only accessed by `with_mut`, while holding the critical section.
+unsafe impl Sync for Exclusive {}
+
+static EXCLUSIVE: Exclusive = Exclusive(Cell::new(false));
+
+/// Execute closure `f` in a critical section, with a unique critical section token.
+///
+/// The unique token allows getting `&mut T` out of a [`MutexMut`](crate::MutexMut) with
+/// [`MutexMut::borrow_mut`](crate::MutexMut::borrow_mut), which makes it a zero-overhead
+/// alternative to `Mutex<RefCell<T>>`.
+///
+/// Nesting critical sections is allowed, including entering this one from
+/// [`with`](crate::with) or entering [`with`](crate::with) from `f`. The shared tokens they
+/// provide can't access the data of a [`MutexMut`](crate::MutexMut).
+///
+/// # Panics
+///
+/// This function panics if it's called from within `f` of another call to `with_mut`, since
+/// both unique tokens could be used to borrow the same data mutably.
+///
+/// This function panics if the given closure `f` panics. In this case
+/// the critical section is released before unwinding.
+///
+/// # Example
+///
+/// ```
+/// use critical_section::MutexMut;
+///
+/// static COUNTER: MutexMut<u32> = MutexMut::new(0);
+///
+/// critical_section::with_mut(|cs| {
+///     *COUNTER.borrow_mut(cs) += 1;
+/// });
+/// # #[cfg(not(feature = "std"))] // needed for `cargo test --features std`
+/// # mod no_std {
+/// #     struct MyCriticalSection;
+/// #     critical_section::set_impl!(MyCriticalSection);
+/// #     unsafe impl critical_section::Impl for MyCriticalSection {
+/// #         unsafe fn acquire() -> () {}
+/// #         unsafe fn release(token: ()) {}
+/// #     }
+/// # }
+/// ```
+#[inline]
+#[track_caller]
+pub fn with_mut<R>(f: impl FnOnce(&mut CriticalSectionMut) -> R) -> R {
+    // Helper for clearing `EXCLUSIVE` even if `f` panics. It's dropped before `_guard`, so while
+    // still holding the critical section.
+    struct ExclusiveGuard;
+
+    impl Drop for ExclusiveGuard {
+        #[inline(always)]
+        fn drop(&mut self) {
+            EXCLUSIVE.0.set(false)
+        }
+    }
+
+    let _guard = unsafe { CriticalSectionGuard::enter() };
+    if EXCLUSIVE.0.replace(true) {
+        panic!("`with_mut` must not be called while a `CriticalSectionMut` exists");
+    }
+    let _exclusive = ExclusiveGuard;
+
+    f(&mut CriticalSectionMut {
+        _private: PhantomData,
+        _not_send_sync: PhantomData,
+    })
+}
+
+/// ``` compile_fail
+/// fn bad(cs: &mut critical_section::CriticalSectionMut) {
+///     let m = critical_section::MutexMut::new(42u32);
+///     let a = m.borrow_mut(cs);
+///     let b = m.borrow_mut(cs);
+///     *a += *b;
+/// }
+/// ```
+#[cfg(doctest)]
+const BorrowMutMustBeUniqueTest: () = ();
+
+/// ``` compile_fail
+/// fn bad(cs: &mut critical_section::CriticalSectionMut) {
+///     let m = critical_section::MutexMut::new(42u32);
+///     let a = m.borrow_mut(cs);
+///     let b = m.borrow(cs);
+///     *a += *b;
+/// }
+/// ```
+#[cfg(doctest)]
+const BorrowMustNotAliasBorrowMutTest: () = ();
+
+/// ``` compile_fail
+/// fn bad(cs: critical_section::CriticalSection) -> u32 {
+///     let m = critical_section::MutexMut::new(42u32);
+///     *m.borrow(cs)
+/// }
+/// ```
+#[cfg(doctest)]
+const SharedTokenMustNotAccessMutexMutTest: () = ();
+
+#[cfg(all(test, feature = "std"))]
+mod tests {
+    use crate as critical_section;
+    use critical_section::{Mutex, MutexMut};
+
+    #[test]
+    fn borrow_mut() {
+        static VALUE: MutexMut<u32> = MutexMut::new(0);
+        static SHARED: Mutex<u32> = Mutex::new(3);
+
+        critical_section::with_mut(|cs| {
+            *VALUE.borrow_mut(cs) += 1;
+            *VALUE.borrow_mut(cs) += 1;
+            assert_eq!(*VALUE.borrow(cs), 2);
+            assert_eq!(*SHARED.borrow(cs.token()), 3);
+        });
+    }
+
+    #[test]
+    fn nested() {
+        critical_section::with(|_| {
+            critical_section::with_mut(|_| critical_section::with(|_| {}));
+        });
+        critical_section::with_mut(|_| {});
+    }
+
+    #[test]
+    #[should_panic(expected = "`with_mut` must not be called while a `CriticalSectionMut` exists")]
+    fn with_mut_in_with_mut() {
+        critical_section::with_mut(|_| {
+            critical_section::with(|_| critical_section::with_mut(|_| {}))
+        });
+    }
+
+    #[test]
+    fn reusable_after_panic() {
+        let _ = std::panic::catch_unwind(|| {
+            critical_section::with_mut(|_| critical_section::with_mut(|_| {}));
+        });
+
+        critical_section::with(|_| {});
+        critical_section::with_mut(|_| {});
+    }
+}
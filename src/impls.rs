@@ -0,0 +1,62 @@
+//! Reusable building blocks for critical section implementations.
+//!
+//! Many critical section implementations follow the same pattern, with only a few
+//! architecture-specific operations differing between them. The types in this module implement
+//! [`Impl`](crate::Impl) generically on top of small traits that capture those operations, so
+//! implementation crates only need to provide the architecture-specific parts and use
+//! [`set_impl!`](crate::set_impl) as usual.
+
+mod checked;
+mod instrumented;
+mod interrupt_disable;
+mod spinlock;
+pub(crate) mod wrapper;
+
+pub use self::checked::{
+    Checked, PanicOnViolation, Violation, ViolationHandler, MAX_CHECKED_DEPTH,
+};
+#[cfg(feature = "std")]
+pub use self::instrumented::StdClock;
+pub use self::instrumented::{CallSiteStats, Clock, Instrumented, Report, MAX_CALL_SITES};
+pub use self::interrupt_disable::{InterruptControl, InterruptDisable};
+pub use self::spinlock::{HwLock, LocalMask, Spinlock};
+
+/// Stores a `bool` in the restore state.
+#[cfg(feature = "restore-state-bool")]
+#[inline(always)]
+fn bool_to_raw(b: bool) -> crate::RawRestoreState {
+    b
+}
+
+/// Stores a `bool` in the restore state.
+#[cfg(any(
+    feature = "restore-state-u8",
+    feature = "restore-state-u16",
+    feature = "restore-state-u32",
+    feature = "restore-state-u64",
+    feature = "restore-state-usize"
+))]
+#[inline(always)]
+fn bool_to_raw(b: bool) -> crate::RawRestoreState {
+    b as crate::RawRestoreState
+}
+
+/// Reads a `bool` stored in the restore state by [`bool_to_raw`].
+#[cfg(feature = "restore-state-bool")]
+#[inline(always)]
+fn raw_to_bool(raw: crate::RawRestoreState) -> bool {
+    raw
+}
+
+/// Reads a `bool` stored in the restore state by [`bool_to_raw`].
+#[cfg(any(
+    feature = "restore-state-u8",
+    feature = "restore-state-u16",
+    feature = "restore-state-u32",
+    feature = "restore-state-u64",
+    feature = "restore-state-usize"
+))]
+#[inline(always)]
+fn raw_to_bool(raw: crate::RawRestoreState) -> bool {
+    raw != 0
+}
@@ -0,0 +1,400 @@
+// `RawRestoreState` is `()` without any `restore-state-*` feature.
+#![allow(clippy::let_unit_value, clippy::unit_arg)]
+
+use core::cell::UnsafeCell;
+use core::fmt;
+use core::marker::PhantomData;
+use core::time::Duration;
+
+use crate::{Impl, RawRestoreState, RestoreState, TryImpl};
+
+/// Maximum nesting depth tracked by [`Checked`].
+///
+/// Deeper critical sections are still counted, but their restore states are not checked.
+pub const MAX_CHECKED_DEPTH: usize = 32;
+
+/// Violation of the [`acquire`](crate::acquire)/[`release`](crate::release) contract, detected
+/// by [`Checked`].
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum Violation {
+    /// `release` was called while no critical section was acquired.
+    ReleaseWithoutAcquire,
+    /// `release` was called with the restore state of an outer critical section, while an inner
+    /// one was still acquired.
+    NotNested {
+        /// Nesting depth of the innermost acquired critical section, which should have been
+        /// released first.
+        expected_depth: usize,
+        /// Nesting depth of the critical section whose restore state was passed to `release`.
+        actual_depth: usize,
+    },
+    /// `release` was called with a restore state that matches neither the one of the innermost
+    /// acquired critical section nor any outer one.
+    MismatchedRestoreState {
+        /// Nesting depth of the critical section being released.
+        depth: usize,
+    },
+    /// Critical sections were still acquired when [`Checked::check_released`] was called.
+    NotReleased {
+        /// Number of critical sections still acquired.
+        depth: usize,
+    },
+}
+
+impl fmt::Display for Violation {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match *self {
+            Violation::ReleaseWithoutAcquire => {
+                f.write_str("`release` called while the critical section is not acquired")
+            }
+            Violation::NotNested {
+                expected_depth,
+                actual_depth,
+            } => write!(
+                f,
+                "`release` called for the critical section at depth {} while the one at depth {} \
+                 is still acquired, critical sections must be released in reverse order",
+                actual_depth, expected_depth
+            ),
+            Violation::MismatchedRestoreState { depth } => write!(
+                f,
+                "`release` called with a restore state that doesn't match the one returned by \
+                 `acquire` for the critical section at depth {}",
+                depth
+            ),
+            Violation::NotReleased { depth } => write!(
+                f,
+                "{} critical section(s) acquired but never released",
+                depth
+            ),
+        }
+    }
+}
+
+/// Handler for violations detected by [`Checked`].
+pub trait ViolationHandler {
+    /// Called with each detected violation.
+    ///
+    /// This is called outside of the critical section when possible, so panicking doesn't leave it
+    /// acquired. If it returns, the offending call is forwarded to the wrapped implementation as
+    /// is, except after [`Violation::ReleaseWithoutAcquire`]: the release is then dropped, since
+    /// the critical section may be held by another thread or core.
+    fn violation(violation: Violation);
+}
+
+/// [`ViolationHandler`] that panics with a message describing the violation.
+///
+/// This is the default handler of [`Checked`].
+pub struct PanicOnViolation;
+
+impl ViolationHandler for PanicOnViolation {
+    fn violation(violation: Violation) {
+        panic!("critical section contract violation: {}", violation)
+    }
+}
+
+/// Critical section implementation wrapper that validates the [`acquire`](crate::acquire)/
+/// [`release`](crate::release) contract.
+///
+/// `Checked<I, H>` forwards to the implementation `I`, and keeps a shadow stack of the restore
+/// states of the acquired critical sections. Every [`Violation`] of the contract it detects is
+/// reported to `H`, which panics by default. This is meant for debug builds and host tests, and
+/// adds some overhead to every acquire and release.
+///
+/// Restore states are compared by value, so violations involving restore states that are equal
+/// can't be detected. With the default `restore-state-none` feature, only
+/// [`Violation::ReleaseWithoutAcquire`] and [`Violation::NotReleased`] are detected.
+///
+/// The shadow stack is only accessed while holding the critical section. If `I` can't tell
+/// whether it is acquired (see [`Impl::is_acquired`]), `release` acquires it once more around
+/// the check, which waits for the holder when called without holding it.
+///
+/// All `Checked` types push to the same shadow stack. Wrapping two implementations in one program
+/// would check the restore states of each against those of the other.
+///
+/// # Example
+///
+/// ```
+/// # #[cfg(not(feature = "std"))] // needed for `cargo test --features std`
+/// # mod no_std {
+/// use critical_section::impls::Checked;
+/// use critical_section::RawRestoreState;
+///
+/// struct MyCriticalSection;
+/// unsafe impl critical_section::Impl for MyCriticalSection {
+///     unsafe fn acquire() -> RawRestoreState {
+///         // ...
+///     }
+///
+///     unsafe fn release(restore_state: RawRestoreState) {
+///         // ...
+///     }
+/// }
+///
+/// #[cfg(debug_assertions)]
+/// critical_section::set_impl!(Checked<MyCriticalSection>);
+/// #[cfg(not(debug_assertions))]
+/// critical_section::set_impl!(MyCriticalSection);
+/// # }
+/// ```
+pub struct Checked<I, H = PanicOnViolation> {
+    _marker: PhantomData<(I, H)>,
+}
+
+struct ShadowStack {
+    depth: UnsafeCell<usize>,
+    states: UnsafeCell<[RawRestoreState; MAX_CHECKED_DEPTH]>,
+}
+
+// SAFETY: `SHADOW_STACK` is only accessed while holding the critical section.
+unsafe impl Sync for ShadowStack {}
+
+static SHADOW_STACK: ShadowStack = ShadowStack {
+    depth: UnsafeCell::new(0),
+    states: UnsafeCell::new([RestoreState::invalid().0; MAX_CHECKED_DEPTH]),
+};
+
+// Not using `==` directly, since clippy doesn't like comparing `()`.
+#[allow(clippy::unit_cmp)]
+#[inline(always)]
+fn same(a: RawRestoreState, b: RawRestoreState) -> bool {
+    a == b
+}
+
+impl<I: Impl, H: ViolationHandler> Checked<I, H> {
+    /// Reports [`Violation::NotReleased`] if critical sections acquired through this wrapper are
+    /// still acquired.
+    ///
+    /// This is meant to be called at the end of a test, when no critical section should be
+    /// acquired by any thread.
+    pub fn check_released() {
+        let depth = unsafe {
+            let state = I::acquire();
+            let depth = *SHADOW_STACK.depth.get();
+            I::release(state);
+            depth
+        };
+        if depth != 0 {
+            H::violation(Violation::NotReleased { depth });
+        }
+    }
+
+    /// Must be called right after acquiring the critical section with `I`.
+    #[inline(always)]
+    unsafe fn push(state: RawRestoreState) {
+        let depth = &mut *SHADOW_STACK.depth.get();
+        if let Some(slot) = (*SHADOW_STACK.states.get()).get_mut(*depth) {
+            *slot = state;
+        }
+        *depth += 1;
+    }
+
+    /// Must be called right before releasing the critical section with `I`.
+    #[inline(always)]
+    unsafe fn pop(state: RawRestoreState) -> Option<Violation> {
+        let depth = &mut *SHADOW_STACK.depth.get();
+        if *depth == 0 {
+            return Some(Violation::ReleaseWithoutAcquire);
+        }
+        let expected_depth = *depth;
+        *depth -= 1;
+
+        let states = &*SHADOW_STACK.states.get();
+        // Critical sections deeper than `MAX_CHECKED_DEPTH` are not tracked.
+        let tracked = states.get(..expected_depth)?;
+        if same(tracked[expected_depth - 1], state) {
+            return None;
+        }
+
+        Some(match tracked.iter().rposition(|s| same(*s, state)) {
+            Some(i) => Violation::NotNested {
+                expected_depth,
+                actual_depth: i + 1,
+            },
+            None => Violation::MismatchedRestoreState {
+                depth: expected_depth,
+            },
+        })
+    }
+}
+
+unsafe impl<I: Impl, H: ViolationHandler> Impl for Checked<I, H> {
+    unsafe fn acquire() -> RawRestoreState {
+        let state = I::acquire();
+        Self::push(state);
+        state
+    }
+
+    unsafe fn release(restore_state: RawRestoreState) {
+        let violation = match I::is_acquired() {
+            Some(true) => Self::pop(restore_state),
+            // Release without acquire can't touch the shadow stack, it may be in use by another
+            // thread holding the critical section.
+            Some(false) => Some(Violation::ReleaseWithoutAcquire),
+            // Nested if the caller holds the critical section, otherwise this waits until no
+            // other thread does, so the shadow stack can't be in use either way.
+            None => {
+                let state = I::acquire();
+                let violation = Self::pop(restore_state);
+                I::release(state);
+                violation
+            }
+        };
+
+        // Releasing would unlock the critical section for whoever holds it, if anyone.
+        if violation == Some(Violation::ReleaseWithoutAcquire) {
+            return H::violation(Violation::ReleaseWithoutAcquire);
+        }
+        I::release(restore_state);
+        if let Some(violation) = violation {
+            H::violation(violation);
+        }
+    }
+
+    #[inline(always)]
+    fn is_acquired() -> Option<bool> {
+        I::is_acquired()
+    }
+
+    #[inline(always)]
+    fn depth() -> Option<usize> {
+        I::depth()
+    }
+}
+
+unsafe impl<I: TryImpl, H: ViolationHandler> TryImpl for Checked<I, H> {
+    unsafe fn try_acquire() -> Option<RawRestoreState> {
+        let state = I::try_acquire()?;
+        Self::push(state);
+        Some(state)
+    }
+
+    unsafe fn try_acquire_for(timeout: Duration) -> Option<RawRestoreState> {
+        let state = I::try_acquire_for(timeout)?;
+        Self::push(state);
+        Some(state)
+    }
+}
+
+#[cfg(all(test, feature = "restore-state-bool"))]
+// Not using `const { .. }` thread local initializers to keep supporting the non-`std` MSRV.
+#[allow(clippy::missing_const_for_thread_local)]
+mod tests {
+    extern crate std;
+
+    use core::cell::{Cell, RefCell};
+    use std::vec::Vec;
+
+    use super::{Checked, Violation, ViolationHandler};
+    use crate::{Impl, RawRestoreState};
+
+    std::thread_local! {
+        static ENABLED: Cell<bool> = Cell::new(true);
+        static VIOLATIONS: RefCell<Vec<Violation>> = RefCell::new(Vec::new());
+    }
+
+    // Interrupt-disabling mock, so nested critical sections get different restore states.
+    struct MockImpl;
+
+    unsafe impl Impl for MockImpl {
+        unsafe fn acquire() -> RawRestoreState {
+            ENABLED.with(|e| e.replace(false))
+        }
+
+        unsafe fn release(restore_state: RawRestoreState) {
+            if restore_state {
+                ENABLED.with(|e| e.set(true));
+            }
+        }
+    }
+
+    struct Record;
+
+    impl ViolationHandler for Record {
+        fn violation(violation: Violation) {
+            VIOLATIONS.with(|v| v.borrow_mut().push(violation));
+        }
+    }
+
+    type MockChecked = Checked<MockImpl, Record>;
+
+    fn violations() -> Vec<Violation> {
+        VIOLATIONS.with(|v| v.borrow_mut().drain(..).collect())
+    }
+
+    // Each step expects the shadow stack left empty by the previous one.
+    #[test]
+    fn checked() {
+        unsafe {
+            // Properly nested.
+            let outer = MockChecked::acquire();
+            let inner = MockChecked::acquire();
+            MockChecked::release(inner);
+            MockChecked::release(outer);
+            MockChecked::check_released();
+            assert_eq!(violations(), []);
+
+            // Release without acquire, which must not be forwarded.
+            ENABLED.with(|e| e.set(false));
+            MockChecked::release(true);
+            assert_eq!(violations(), [Violation::ReleaseWithoutAcquire]);
+            assert!(!ENABLED.with(|e| e.get()));
+            ENABLED.with(|e| e.set(true));
+
+            // Not nested.
+            let outer = MockChecked::acquire();
+            let inner = MockChecked::acquire();
+            MockChecked::release(outer);
+            MockChecked::release(inner);
+            assert_eq!(
+                violations(),
+                [
+                    Violation::NotNested {
+                        expected_depth: 2,
+                        actual_depth: 1,
+                    },
+                    Violation::MismatchedRestoreState { depth: 1 },
+                ]
+            );
+
+            // Unknown restore state.
+            ENABLED.with(|e| e.set(false));
+            let state = MockChecked::acquire();
+            MockChecked::release(!state);
+            assert_eq!(
+                violations(),
+                [Violation::MismatchedRestoreState { depth: 1 }]
+            );
+
+            // Missing release.
+            let state = MockChecked::acquire();
+            MockChecked::check_released();
+            assert_eq!(violations(), [Violation::NotReleased { depth: 1 }]);
+            MockChecked::release(state);
+        }
+    }
+
+    #[test]
+    #[should_panic(
+        expected = "critical section contract violation: `release` called while \
+                               the critical section is not acquired"
+    )]
+    fn panic_on_violation() {
+        struct NotAcquiredImpl;
+
+        unsafe impl Impl for NotAcquiredImpl {
+            unsafe fn acquire() -> RawRestoreState {
+                false
+            }
+
+            unsafe fn release(_restore_state: RawRestoreState) {}
+
+            fn is_acquired() -> Option<bool> {
+                Some(false)
+            }
+        }
+
+        unsafe { Checked::<NotAcquiredImpl>::release(false) }
+    }
+}
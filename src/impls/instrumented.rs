@@ -0,0 +1,362 @@
+// `RawRestoreState` is `()` without any `restore-state-*` feature.
+#![allow(clippy::let_unit_value, clippy::unit_arg)]
+
+use core::cell::UnsafeCell;
+use core::fmt;
+use core::marker::PhantomData;
+use core::panic::Location;
+
+use super::wrapper::{forward_impl, with_location, Frame, Frames, Located};
+use crate::{CriticalSection, Impl};
+
+/// Maximum number of call sites tracked by [`Instrumented`].
+///
+/// Critical sections entered from other call sites are only counted, see [`Report::untracked`].
+pub const MAX_CALL_SITES: usize = 32;
+
+// Critical sections nested deeper than this are not timed.
+const MAX_DEPTH: usize = 16;
+
+/// Source of time for [`Instrumented`].
+pub trait Clock {
+    /// Returns the current time, in ticks of an arbitrary unit.
+    ///
+    /// The value must increase monotonically, but may wrap around.
+    fn now() -> u64;
+}
+
+/// [`Clock`] based on `std::time::Instant`, with nanosecond ticks.
+#[cfg(feature = "std")]
+pub struct StdClock;
+
+#[cfg(feature = "std")]
+impl Clock for StdClock {
+    fn now() -> u64 {
+        use std::sync::{Mutex, PoisonError};
+        use std::time::Instant;
+
+        // Not using `crate::OnceCell`, which would enter the critical section recursively.
+        static START: Mutex<Option<Instant>> = Mutex::new(None);
+
+        let mut start = START.lock().unwrap_or_else(PoisonError::into_inner);
+        start.get_or_insert_with(Instant::now).elapsed().as_nanos() as u64
+    }
+}
+
+/// Statistics recorded by [`Instrumented`] for a call site.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct CallSiteStats {
+    /// Location of the call to [`Instrumented::with`], or `None` for critical sections entered
+    /// with other functions, such as [`crate::with`].
+    pub location: Option<&'static Location<'static>>,
+    /// Number of times the critical section was entered from this call site.
+    pub count: u64,
+    /// Total time the critical section was held, in [`Clock`] ticks.
+    pub total: u64,
+    /// Maximum time the critical section was held, in [`Clock`] ticks.
+    pub max: u64,
+    /// Maximum nesting depth of the critical section entered from this call site, `1` for the
+    /// outermost one.
+    pub max_depth: usize,
+}
+
+impl fmt::Display for CallSiteStats {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.location {
+            Some(location) => write!(f, "{}", location)?,
+            None => f.write_str("<unknown>")?,
+        }
+        write!(
+            f,
+            ": {} entries, max {} ticks, total {} ticks, max depth {}",
+            self.count, self.max, self.total, self.max_depth
+        )
+    }
+}
+
+/// Statistics returned by [`Instrumented::report`].
+#[derive(Clone, Copy, Debug)]
+pub struct Report {
+    // Sorted by decreasing maximum hold time, with empty slots last.
+    call_sites: [Option<CallSiteStats>; MAX_CALL_SITES],
+    untracked: u64,
+}
+
+impl Report {
+    /// Returns the statistics of each call site, by decreasing maximum hold time.
+    pub fn call_sites(&self) -> impl Iterator<Item = &CallSiteStats> {
+        self.call_sites.iter().flatten()
+    }
+
+    /// Returns the number of critical sections that were not recorded, because they were entered
+    /// from more than [`MAX_CALL_SITES`] call sites.
+    pub fn untracked(&self) -> u64 {
+        self.untracked
+    }
+}
+
+impl fmt::Display for Report {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for stats in self.call_sites() {
+            writeln!(f, "{}", stats)?;
+        }
+        if self.untracked != 0 {
+            writeln!(f, "<untracked>: {} entries", self.untracked)?;
+        }
+        Ok(())
+    }
+}
+
+/// Critical section implementation wrapper that measures how long the critical section is held.
+///
+/// `Instrumented<I, C>` forwards to the implementation `I`, and uses the clock `C` to measure
+/// the time between each acquire and the matching release. The statistics are grouped by call
+/// site, and can be retrieved with [`Instrumented::report`]. This makes it possible to find the
+/// critical sections responsible for interrupt latency.
+///
+/// Only critical sections entered with [`Instrumented::with`] know their call site. The ones
+/// entered with other functions, such as [`crate::with`], are all grouped under an unknown
+/// location.
+///
+/// There is a single set of statistics: [`Instrumented::report`] returns the critical sections of
+/// every `Instrumented` type in the program, not only those of `Self`.
+///
+/// # Example
+///
+/// ```
+/// use critical_section::impls::{Clock, Instrumented};
+///
+/// struct MyCriticalSection;
+/// unsafe impl critical_section::Impl for MyCriticalSection {
+///     unsafe fn acquire() -> critical_section::RawRestoreState {
+///         // ...
+/// #       Default::default()
+///     }
+///
+///     unsafe fn release(restore_state: critical_section::RawRestoreState) {
+///         // ...
+///     }
+/// }
+///
+/// struct CycleCounter;
+/// impl Clock for CycleCounter {
+///     fn now() -> u64 {
+///         // Read the cycle counter.
+/// #       0
+///     }
+/// }
+///
+/// type Cs = Instrumented<MyCriticalSection, CycleCounter>;
+/// # #[cfg(not(feature = "std"))] // needed for `cargo test --features std`
+/// critical_section::set_impl!(Cs);
+///
+/// # #[cfg(not(feature = "std"))]
+/// # fn main() {
+/// Cs::with(|cs| {
+///     // ...
+/// });
+///
+/// for stats in Cs::report().call_sites() {
+///     // Log `stats`.
+/// #   assert!(stats.location.is_some());
+/// #   assert_eq!(stats.count, 1);
+/// }
+/// # }
+/// # #[cfg(feature = "std")]
+/// # fn main() {}
+/// ```
+pub struct Instrumented<I, C> {
+    _marker: PhantomData<(I, C)>,
+}
+
+// Time at which each acquired critical section was entered.
+static FRAMES: Frames<u64, MAX_DEPTH> = Frames::new(
+    [Frame {
+        location: None,
+        data: 0,
+    }; MAX_DEPTH],
+);
+
+struct Stats {
+    call_sites: UnsafeCell<[Option<CallSiteStats>; MAX_CALL_SITES]>,
+    untracked: UnsafeCell<u64>,
+}
+
+// SAFETY: `STATS` is only accessed while holding the critical section.
+unsafe impl Sync for Stats {}
+
+static STATS: Stats = Stats {
+    call_sites: UnsafeCell::new([None; MAX_CALL_SITES]),
+    untracked: UnsafeCell::new(0),
+};
+
+impl<I: Impl, C: Clock> Instrumented<I, C> {
+    /// Execute closure `f` in a critical section, recording statistics for the call site.
+    ///
+    /// This is the same as [`crate::with`], except that the statistics reported by
+    /// [`Instrumented::report`] are attributed to the caller of this function.
+    ///
+    /// # Panics
+    ///
+    /// This function panics if the given closure `f` panics. In this case
+    /// the critical section is released before unwinding.
+    #[inline]
+    #[track_caller]
+    pub fn with<R>(f: impl FnOnce(CriticalSection) -> R) -> R {
+        with_location::<Self, _, _>(f)
+    }
+
+    /// Returns the statistics recorded since the last [`Instrumented::reset`].
+    pub fn report() -> Report {
+        let mut report = unsafe {
+            let state = I::acquire();
+            let report = Report {
+                call_sites: *STATS.call_sites.get(),
+                untracked: *STATS.untracked.get(),
+            };
+            I::release(state);
+            report
+        };
+        report
+            .call_sites
+            .sort_unstable_by_key(|s| core::cmp::Reverse(s.map(|s| s.max)));
+        report
+    }
+
+    /// Clears the recorded statistics.
+    pub fn reset() {
+        unsafe {
+            let state = I::acquire();
+            *STATS.call_sites.get() = [None; MAX_CALL_SITES];
+            *STATS.untracked.get() = 0;
+            I::release(state);
+        }
+    }
+
+    /// Must be called right after acquiring the critical section with `I`.
+    #[inline(always)]
+    unsafe fn push() {
+        FRAMES.push(C::now());
+    }
+
+    /// Must be called right before releasing the critical section with `I`.
+    #[inline(always)]
+    unsafe fn pop() {
+        let (depth, frame) = match FRAMES.pop() {
+            (depth, Some(frame)) => (depth, frame),
+            // Deeper critical sections are not timed.
+            _ => return,
+        };
+        let held = C::now().wrapping_sub(frame.data);
+
+        let call_sites = &mut *STATS.call_sites.get();
+        let slot = call_sites.iter_mut().find(|s| match s {
+            Some(s) => s.location == frame.location,
+            None => true,
+        });
+        match slot {
+            Some(Some(stats)) => {
+                stats.count += 1;
+                stats.total = stats.total.wrapping_add(held);
+                stats.max = stats.max.max(held);
+                stats.max_depth = stats.max_depth.max(depth);
+            }
+            Some(slot) => {
+                *slot = Some(CallSiteStats {
+                    location: frame.location,
+                    count: 1,
+                    total: held,
+                    max: held,
+                    max_depth: depth,
+                })
+            }
+            None => *STATS.untracked.get() += 1,
+        }
+    }
+}
+
+impl<I: Impl, C: Clock> Located for Instrumented<I, C> {
+    #[inline(always)]
+    unsafe fn set_location(location: &'static Location<'static>) {
+        FRAMES.set_location(location);
+    }
+}
+
+forward_impl!(Instrumented<I, C: Clock>);
+
+#[cfg(test)]
+mod tests {
+    extern crate std;
+
+    use core::panic::Location;
+    use core::sync::atomic::{AtomicU64, Ordering};
+    use std::vec::Vec;
+
+    use super::{Clock, Instrumented};
+    use crate::impls::wrapper::{Located, NoopImpl};
+    use crate::Impl;
+
+    static NOW: AtomicU64 = AtomicU64::new(0);
+
+    // Only moves forward with `advance`, so hold times are exact.
+    struct ManualClock;
+
+    impl Clock for ManualClock {
+        fn now() -> u64 {
+            NOW.load(Ordering::Relaxed)
+        }
+    }
+
+    type MockInstrumented = Instrumented<NoopImpl, ManualClock>;
+
+    fn advance(ticks: u64) {
+        NOW.fetch_add(ticks, Ordering::Relaxed);
+    }
+
+    #[track_caller]
+    fn enter(ticks: u64) {
+        unsafe {
+            let state = MockInstrumented::acquire();
+            MockInstrumented::set_location(Location::caller());
+            advance(ticks);
+            MockInstrumented::release(state);
+        }
+    }
+
+    // Only this test records statistics, so it doesn't need to filter out other call sites.
+    #[test]
+    fn instrumented() {
+        let line = line!() + 2;
+        for ticks in [10, 30] {
+            enter(ticks);
+        }
+        unsafe {
+            let outer = MockInstrumented::acquire();
+            advance(5);
+            enter(100);
+            MockInstrumented::release(outer);
+        }
+
+        let report = MockInstrumented::report();
+        let stats: Vec<_> = report.call_sites().collect();
+        assert_eq!(stats.len(), 3);
+        assert_eq!(report.untracked(), 0);
+
+        // Sorted by maximum hold time.
+        assert_eq!(stats[0].location, None);
+        assert_eq!(
+            (stats[0].count, stats[0].max, stats[0].max_depth),
+            (1, 105, 1)
+        );
+        assert_eq!(stats[1].location.map(|l| l.line()), Some(line + 5));
+        assert_eq!(
+            (stats[1].count, stats[1].max, stats[1].max_depth),
+            (1, 100, 2)
+        );
+        assert_eq!(stats[2].location.map(|l| l.line()), Some(line));
+        assert_eq!((stats[2].count, stats[2].total, stats[2].max), (2, 40, 30));
+
+        MockInstrumented::reset();
+        assert_eq!(MockInstrumented::report().call_sites().count(), 0);
+    }
+}
@@ -0,0 +1,191 @@
+use core::marker::PhantomData;
+#[cfg(any(
+    feature = "restore-state-bool",
+    feature = "restore-state-u8",
+    feature = "restore-state-u16",
+    feature = "restore-state-u32",
+    feature = "restore-state-u64",
+    feature = "restore-state-usize"
+))]
+use core::sync::atomic::{compiler_fence, Ordering};
+
+/// Control of the global interrupt-enable flag of a single-core architecture.
+///
+/// # Safety
+///
+/// [`InterruptControl::disable`] must prevent any code other than the current one from running
+/// until the matching [`InterruptControl::restore`].
+pub unsafe trait InterruptControl {
+    /// Disables interrupts, returning whether they were enabled before.
+    fn disable() -> bool;
+
+    /// Restores interrupts to the state returned by [`InterruptControl::disable`].
+    ///
+    /// Must enable interrupts if `was_enabled` is `true`, and do nothing otherwise.
+    ///
+    /// # Safety
+    ///
+    /// `was_enabled` must be the value returned by the matching call to
+    /// [`InterruptControl::disable`].
+    unsafe fn restore(was_enabled: bool);
+}
+
+/// Single-core critical section implementation that disables interrupts.
+///
+/// Acquiring the critical section disables interrupts with `C`, and releasing it re-enables them
+/// only if they were enabled before, so nesting works as expected. Compiler fences are inserted
+/// so that accesses inside the critical section can't be moved outside of it.
+///
+/// The previous interrupt state is kept in the restore state, so this requires one of the
+/// `restore-state-bool`, `restore-state-u8`, ..., `restore-state-usize` Cargo features.
+///
+/// This is only sound on single-core systems, see [`Spinlock`](super::Spinlock) for multicore
+/// systems.
+///
+/// # Example
+///
+/// ```
+/// # #[cfg(not(feature = "std"))] // needed for `cargo test --features std`
+/// # mod no_std {
+/// use critical_section::impls::{InterruptControl, InterruptDisable};
+///
+/// struct MyArch;
+/// unsafe impl InterruptControl for MyArch {
+///     fn disable() -> bool {
+///         // Read the interrupt-enable flag, then clear it.
+/// #       false
+///     }
+///     unsafe fn restore(was_enabled: bool) {
+///         // Set the interrupt-enable flag if `was_enabled`.
+///     }
+/// }
+///
+/// # #[cfg(feature = "restore-state-bool")]
+/// critical_section::set_impl!(InterruptDisable<MyArch>);
+/// # }
+/// ```
+pub struct InterruptDisable<C> {
+    _marker: PhantomData<C>,
+}
+
+#[cfg(any(
+    feature = "restore-state-bool",
+    feature = "restore-state-u8",
+    feature = "restore-state-u16",
+    feature = "restore-state-u32",
+    feature = "restore-state-u64",
+    feature = "restore-state-usize"
+))]
+unsafe impl<C: InterruptControl> crate::Impl for InterruptDisable<C> {
+    unsafe fn acquire() -> crate::RawRestoreState {
+        let was_enabled = C::disable();
+        // Prevent the compiler from moving accesses in the critical section before this point.
+        compiler_fence(Ordering::SeqCst);
+        super::bool_to_raw(was_enabled)
+    }
+
+    unsafe fn release(restore_state: crate::RawRestoreState) {
+        // Prevent the compiler from moving accesses in the critical section after this point.
+        compiler_fence(Ordering::SeqCst);
+        C::restore(super::raw_to_bool(restore_state))
+    }
+}
+
+#[cfg(all(
+    test,
+    any(
+        feature = "restore-state-bool",
+        feature = "restore-state-u8",
+        feature = "restore-state-u16",
+        feature = "restore-state-u32",
+        feature = "restore-state-u64",
+        feature = "restore-state-usize"
+    )
+))]
+// Not using `const { .. }` thread local initializers to keep supporting the non-`std` MSRV.
+#[allow(clippy::missing_const_for_thread_local)]
+mod tests {
+    extern crate std;
+
+    use core::cell::Cell;
+
+    use super::{InterruptControl, InterruptDisable};
+    use crate::Impl;
+
+    // The interrupt-enable flag is thread-local, so tests can run in parallel. Tests may also run
+    // one after another on the same thread, so each one starts with `reset`.
+    std::thread_local! {
+        static ENABLED: Cell<bool> = Cell::new(true);
+        static ENABLE_COUNT: Cell<usize> = Cell::new(0);
+    }
+
+    struct MockControl;
+
+    unsafe impl InterruptControl for MockControl {
+        fn disable() -> bool {
+            ENABLED.with(|e| e.replace(false))
+        }
+
+        unsafe fn restore(was_enabled: bool) {
+            if was_enabled {
+                ENABLED.with(|e| e.set(true));
+                ENABLE_COUNT.with(|c| c.set(c.get() + 1));
+            }
+        }
+    }
+
+    type MockImpl = InterruptDisable<MockControl>;
+
+    fn reset(enabled: bool) {
+        ENABLED.with(|e| e.set(enabled));
+        ENABLE_COUNT.with(|c| c.set(0));
+    }
+
+    fn enabled() -> bool {
+        ENABLED.with(|e| e.get())
+    }
+
+    fn enable_count() -> usize {
+        ENABLE_COUNT.with(|c| c.get())
+    }
+
+    #[test]
+    fn acquire_release() {
+        reset(true);
+        unsafe {
+            let state = MockImpl::acquire();
+            assert!(!enabled());
+            MockImpl::release(state);
+        }
+        assert!(enabled());
+        assert_eq!(enable_count(), 1);
+    }
+
+    #[test]
+    fn nested() {
+        reset(true);
+        unsafe {
+            let outer = MockImpl::acquire();
+            let inner = MockImpl::acquire();
+            MockImpl::release(inner);
+            // Releasing the nested critical section must not enable interrupts.
+            assert!(!enabled());
+            assert_eq!(enable_count(), 0);
+            MockImpl::release(outer);
+        }
+        assert!(enabled());
+        assert_eq!(enable_count(), 1);
+    }
+
+    #[test]
+    fn already_disabled() {
+        reset(false);
+        unsafe {
+            let state = MockImpl::acquire();
+            MockImpl::release(state);
+        }
+        // Interrupts were disabled before acquiring, so they must stay disabled.
+        assert!(!enabled());
+        assert_eq!(enable_count(), 0);
+    }
+}
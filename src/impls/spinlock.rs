@@ -0,0 +1,342 @@
+use core::marker::PhantomData;
+
+/// Masking of interrupts on the current core.
+///
+/// # Safety
+///
+/// [`LocalMask::mask`] must prevent any code other than the current one from running on the
+/// current core until the matching [`LocalMask::restore`].
+///
+/// [`LocalMask::core_id`] must return a different value on each core sharing the [`HwLock`].
+pub unsafe trait LocalMask {
+    /// Returns the identifier of the current core.
+    ///
+    /// Must be less than 255.
+    fn core_id() -> u8;
+
+    /// Masks interrupts on the current core, returning whether they were unmasked before.
+    ///
+    /// # Safety
+    ///
+    /// Must be paired with a call to [`LocalMask::restore`].
+    unsafe fn mask() -> bool;
+
+    /// Restores the interrupt mask to the state returned by [`LocalMask::mask`].
+    ///
+    /// # Safety
+    ///
+    /// `was_unmasked` must be the value returned by the matching call to [`LocalMask::mask`].
+    unsafe fn restore(was_unmasked: bool);
+}
+
+/// Lock shared by all cores, such as a hardware spinlock.
+///
+/// # Safety
+///
+/// The lock must be held by at most one core at a time. Taking it must have at least
+/// [`Acquire`](core::sync::atomic::Ordering::Acquire) semantics, and releasing it at least
+/// [`Release`](core::sync::atomic::Ordering::Release) semantics.
+pub unsafe trait HwLock {
+    /// Tries to take the lock, without blocking. Returns whether it was taken.
+    fn try_lock() -> bool;
+
+    /// Releases the lock.
+    ///
+    /// # Safety
+    ///
+    /// Must only be called by the core that has taken the lock.
+    unsafe fn unlock();
+}
+
+/// Multicore critical section implementation.
+///
+/// Acquiring the critical section masks interrupts on the current core with `L`, then takes the
+/// cross-core lock `S`, spinning until it is available. Releasing it undoes both in reverse order.
+/// The critical section is reentrant: the core currently owning the lock can acquire it again
+/// without deadlocking.
+///
+/// Whether an acquire took the lock and whether interrupts were unmasked before are packed into
+/// the restore state, so this requires one of the `restore-state-u8`, ..., `restore-state-usize`
+/// Cargo features. The core owning the lock, needed to detect reentrancy, is kept in a static that
+/// doesn't depend on `S`: a core holding one `Spinlock` would see any other as already acquired.
+///
+/// # Example
+///
+/// ```
+/// # #[cfg(not(feature = "std"))] // needed for `cargo test --features std`
+/// # mod no_std {
+/// use critical_section::impls::{HwLock, LocalMask, Spinlock};
+///
+/// struct MyMask;
+/// unsafe impl LocalMask for MyMask {
+///     fn core_id() -> u8 {
+///         // Read the core ID register.
+/// #       0
+///     }
+///     unsafe fn mask() -> bool {
+///         // Disable interrupts, returning whether they were enabled.
+/// #       false
+///     }
+///     unsafe fn restore(was_unmasked: bool) {
+///         // Enable interrupts if `was_unmasked`.
+///     }
+/// }
+///
+/// struct MyLock;
+/// unsafe impl HwLock for MyLock {
+///     fn try_lock() -> bool {
+///         // Try to claim the hardware spinlock.
+/// #       true
+///     }
+///     unsafe fn unlock() {
+///         // Release the hardware spinlock.
+///     }
+/// }
+///
+/// # #[cfg(feature = "restore-state-u8")]
+/// critical_section::set_impl!(Spinlock<MyMask, MyLock>);
+/// # }
+/// ```
+pub struct Spinlock<L, S> {
+    _marker: PhantomData<(L, S)>,
+}
+
+// Packing the restore state needs at least two bits.
+#[cfg(any(
+    feature = "restore-state-u8",
+    feature = "restore-state-u16",
+    feature = "restore-state-u32",
+    feature = "restore-state-u64",
+    feature = "restore-state-usize"
+))]
+// The casts from and to `RawRestoreState` are no-ops with `restore-state-u8`.
+#[allow(clippy::unnecessary_cast)]
+mod imp {
+    use core::sync::atomic::{compiler_fence, AtomicU8, Ordering};
+
+    use super::{HwLock, LocalMask, Spinlock};
+    use crate::{Impl, RawRestoreState};
+
+    // `core_id() + 1` of the core owning the lock, or 0 if it's not owned.
+    //
+    // This is read by cores not owning the lock to detect reentrancy, so it must be atomic. Only
+    // 8-bit loads and stores are needed, which are available on all targets with multiple cores.
+    static OWNER: AtomicU8 = AtomicU8::new(0);
+
+    // Bits of the restore state.
+    // Set if the acquire took the lock, which is the case for the outermost one on each core.
+    const LOCKED: u8 = 1 << 0;
+    // Set if interrupts were unmasked before the acquire. Never set for nested ones, since the
+    // outer one masked them already.
+    const UNMASKED: u8 = 1 << 1;
+
+    impl<L: LocalMask, S: HwLock> Spinlock<L, S> {
+        #[inline(always)]
+        fn owner_id() -> u8 {
+            let core_id = L::core_id();
+            assert!(
+                core_id < u8::MAX,
+                "`LocalMask::core_id` must be less than 255"
+            );
+            core_id + 1
+        }
+    }
+
+    unsafe impl<L: LocalMask, S: HwLock> Impl for Spinlock<L, S> {
+        unsafe fn acquire() -> RawRestoreState {
+            // Before masking interrupts, so they aren't left masked if this panics.
+            let me = Self::owner_id();
+            // Mask interrupts first, so that an interrupt handler can't try to acquire the lock
+            // while this core holds it, which would deadlock.
+            let mut state = if L::mask() { UNMASKED } else { 0 };
+
+            // Only the current core can set OWNER to its own ID, so there's no race here.
+            if OWNER.load(Ordering::Relaxed) != me {
+                while !S::try_lock() {
+                    core::hint::spin_loop();
+                }
+                compiler_fence(Ordering::SeqCst);
+
+                OWNER.store(me, Ordering::Relaxed);
+                state |= LOCKED;
+            }
+
+            state as RawRestoreState
+        }
+
+        unsafe fn release(restore_state: RawRestoreState) {
+            let state = restore_state as u8;
+            if state & LOCKED != 0 {
+                OWNER.store(0, Ordering::Relaxed);
+
+                compiler_fence(Ordering::SeqCst);
+                S::unlock();
+            }
+            L::restore(state & UNMASKED != 0);
+        }
+
+        fn is_acquired() -> Option<bool> {
+            Some(OWNER.load(Ordering::Relaxed) == Self::owner_id())
+        }
+    }
+}
+
+#[cfg(all(
+    test,
+    any(
+        feature = "restore-state-u8",
+        feature = "restore-state-u16",
+        feature = "restore-state-u32",
+        feature = "restore-state-u64",
+        feature = "restore-state-usize"
+    )
+))]
+// Not using `const { .. }` thread local initializers to keep supporting the non-`std` MSRV.
+#[allow(clippy::missing_const_for_thread_local)]
+mod tests {
+    extern crate std;
+
+    use core::cell::{Cell, UnsafeCell};
+    use core::sync::atomic::{AtomicBool, AtomicU8, Ordering};
+    use std::thread;
+    use std::vec::Vec;
+
+    use super::{HwLock, LocalMask, Spinlock};
+    use crate::Impl;
+
+    // Threads stand in for cores, and a thread-local flag for their interrupt mask.
+    struct MockMask;
+
+    static NEXT_CORE_ID: AtomicU8 = AtomicU8::new(0);
+
+    std::thread_local! {
+        static CORE_ID: u8 = NEXT_CORE_ID.fetch_add(1, Ordering::Relaxed);
+        static MASKED: Cell<bool> = Cell::new(false);
+    }
+
+    unsafe impl LocalMask for MockMask {
+        fn core_id() -> u8 {
+            CORE_ID.with(|id| *id)
+        }
+
+        unsafe fn mask() -> bool {
+            !MASKED.with(|m| m.replace(true))
+        }
+
+        unsafe fn restore(was_unmasked: bool) {
+            if was_unmasked {
+                MASKED.with(|m| m.set(false));
+            }
+        }
+    }
+
+    struct MockLock;
+
+    static LOCKED: AtomicBool = AtomicBool::new(false);
+
+    unsafe impl HwLock for MockLock {
+        fn try_lock() -> bool {
+            LOCKED
+                .compare_exchange(false, true, Ordering::Acquire, Ordering::Relaxed)
+                .is_ok()
+        }
+
+        unsafe fn unlock() {
+            LOCKED.store(false, Ordering::Release);
+        }
+    }
+
+    type MockSpinlock = Spinlock<MockMask, MockLock>;
+
+    struct Counter(UnsafeCell<usize>);
+    unsafe impl Sync for Counter {}
+
+    fn masked() -> bool {
+        MASKED.with(|m| m.get())
+    }
+
+    #[test]
+    fn spinlock() {
+        const THREADS: usize = 4;
+        const ITERATIONS: usize = 250;
+
+        static COUNTER: Counter = Counter(UnsafeCell::new(0));
+
+        let threads: Vec<_> = (0..THREADS)
+            .map(|_| {
+                thread::spawn(|| {
+                    for _ in 0..ITERATIONS {
+                        unsafe {
+                            let outer = MockSpinlock::acquire();
+                            assert!(masked());
+                            assert_eq!(MockSpinlock::is_acquired(), Some(true));
+
+                            let inner = MockSpinlock::acquire();
+                            assert_ne!(inner, outer);
+                            // Non-atomic read-modify-write, only correct with mutual exclusion.
+                            let value = *COUNTER.0.get();
+                            thread::yield_now();
+                            *COUNTER.0.get() = value + 1;
+                            MockSpinlock::release(inner);
+
+                            // Releasing the nested critical section must not unmask interrupts.
+                            assert!(masked());
+                            assert_eq!(MockSpinlock::is_acquired(), Some(true));
+                            MockSpinlock::release(outer);
+                            assert!(!masked());
+                            assert_eq!(MockSpinlock::is_acquired(), Some(false));
+                        }
+                    }
+                })
+            })
+            .collect();
+        for t in threads {
+            t.join().unwrap();
+        }
+
+        assert_eq!(unsafe { *COUNTER.0.get() }, THREADS * ITERATIONS);
+        assert!(!LOCKED.load(Ordering::Relaxed));
+    }
+
+    #[test]
+    fn already_masked() {
+        thread::spawn(|| unsafe {
+            MASKED.with(|m| m.set(true));
+            let state = MockSpinlock::acquire();
+            MockSpinlock::release(state);
+            // Interrupts were masked before acquiring, so they must stay masked.
+            assert!(masked());
+            assert_eq!(MockSpinlock::is_acquired(), Some(false));
+        })
+        .join()
+        .unwrap();
+    }
+
+    #[test]
+    fn invalid_core_id() {
+        struct BadMask;
+
+        unsafe impl LocalMask for BadMask {
+            fn core_id() -> u8 {
+                u8::MAX
+            }
+
+            unsafe fn mask() -> bool {
+                MockMask::mask()
+            }
+
+            unsafe fn restore(was_unmasked: bool) {
+                MockMask::restore(was_unmasked)
+            }
+        }
+
+        thread::spawn(|| {
+            let result =
+                std::panic::catch_unwind(|| unsafe { Spinlock::<BadMask, MockLock>::acquire() });
+            assert!(result.is_err());
+            assert!(!masked());
+        })
+        .join()
+        .unwrap();
+    }
+}
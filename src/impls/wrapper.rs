@@ -0,0 +1,175 @@
+//! Parts shared by the wrappers that record critical sections by call site, [`Instrumented`] and
+//! `Traced`.
+//!
+//! [`Instrumented`]: super::Instrumented
+
+use core::cell::UnsafeCell;
+use core::panic::Location;
+
+use crate::CriticalSection;
+
+/// Frame of an acquired critical section.
+#[derive(Clone, Copy)]
+pub(crate) struct Frame<T> {
+    /// Call site the critical section is attributed to, see [`with_location`].
+    pub(crate) location: Option<&'static Location<'static>>,
+    /// Wrapper-specific data.
+    pub(crate) data: T,
+}
+
+/// Stack of the frames of the acquired critical sections.
+///
+/// Only the `N` outermost frames are kept, deeper critical sections are only counted.
+pub(crate) struct Frames<T, const N: usize> {
+    depth: UnsafeCell<usize>,
+    frames: UnsafeCell<[Frame<T>; N]>,
+}
+
+// SAFETY: the frames are only accessed while holding the critical section.
+unsafe impl<T: Send, const N: usize> Sync for Frames<T, N> {}
+
+impl<T, const N: usize> Frames<T, N> {
+    pub(crate) const fn new(frames: [Frame<T>; N]) -> Self {
+        Frames {
+            depth: UnsafeCell::new(0),
+            frames: UnsafeCell::new(frames),
+        }
+    }
+}
+
+impl<T: Copy, const N: usize> Frames<T, N> {
+    /// Returns the nesting depth of the innermost acquired critical section, `0` if there's none.
+    ///
+    /// Must be called while holding the critical section.
+    #[cfg(feature = "trace-buffer")]
+    #[inline(always)]
+    pub(crate) unsafe fn depth(&self) -> usize {
+        *self.depth.get()
+    }
+
+    /// Pushes the frame of a newly acquired critical section, returning its depth.
+    ///
+    /// Must be called right after acquiring the critical section.
+    #[inline(always)]
+    pub(crate) unsafe fn push(&self, data: T) -> usize {
+        let depth = &mut *self.depth.get();
+        if let Some(frame) = (*self.frames.get()).get_mut(*depth) {
+            *frame = Frame {
+                location: None,
+                data,
+            };
+        }
+        *depth += 1;
+        *depth
+    }
+
+    /// Pops the frame of the innermost critical section, returning its depth and the frame, if
+    /// it was kept.
+    ///
+    /// Must be called right before releasing the critical section.
+    #[inline(always)]
+    pub(crate) unsafe fn pop(&self) -> (usize, Option<Frame<T>>) {
+        let depth = &mut *self.depth.get();
+        let frame = (*self.frames.get()).get(*depth - 1).copied();
+        *depth -= 1;
+        (*depth + 1, frame)
+    }
+
+    /// Attributes the innermost acquired critical section to `location`, returning its data.
+    ///
+    /// Must be called while holding the critical section.
+    #[inline(always)]
+    pub(crate) unsafe fn set_location(&self, location: &'static Location<'static>) -> Option<T> {
+        let depth = *self.depth.get();
+        let frame = depth
+            .checked_sub(1)
+            .and_then(|i| (*self.frames.get()).get_mut(i))?;
+        frame.location = Some(location);
+        Some(frame.data)
+    }
+}
+
+/// Wrapper implementation that can attribute critical sections to call sites.
+pub(crate) trait Located {
+    /// Attributes the innermost acquired critical section to `location`.
+    ///
+    /// Must be called while holding the critical section. Must do nothing if the wrapper isn't
+    /// the registered implementation, since then it has no acquired critical section.
+    unsafe fn set_location(location: &'static Location<'static>);
+}
+
+/// Executes `f` in a critical section attributed to the caller by `W`.
+#[inline]
+#[track_caller]
+pub(crate) fn with_location<W, R, F>(f: F) -> R
+where
+    W: Located,
+    F: FnOnce(CriticalSection) -> R,
+{
+    let location = Location::caller();
+    crate::with(|cs| {
+        // SAFETY: the critical section is held.
+        unsafe { W::set_location(location) };
+        f(cs)
+    })
+}
+
+/// Implements [`Impl`](crate::Impl) and [`TryImpl`](crate::TryImpl) for a wrapper around the
+/// implementation `I`, calling `Self::push()` right after acquiring the critical section and
+/// `Self::pop()` right before releasing it.
+macro_rules! forward_impl {
+    ($wrapper:ident<I, $param:ident: $bound:path>) => {
+        unsafe impl<I: $crate::Impl, $param: $bound> $crate::Impl for $wrapper<I, $param> {
+            unsafe fn acquire() -> $crate::RawRestoreState {
+                let state = I::acquire();
+                Self::push();
+                state
+            }
+
+            unsafe fn release(restore_state: $crate::RawRestoreState) {
+                Self::pop();
+                I::release(restore_state)
+            }
+
+            #[inline(always)]
+            fn is_acquired() -> Option<bool> {
+                I::is_acquired()
+            }
+
+            #[inline(always)]
+            fn depth() -> Option<usize> {
+                I::depth()
+            }
+        }
+
+        unsafe impl<I: $crate::TryImpl, $param: $bound> $crate::TryImpl for $wrapper<I, $param> {
+            unsafe fn try_acquire() -> Option<$crate::RawRestoreState> {
+                let state = I::try_acquire()?;
+                Self::push();
+                Some(state)
+            }
+
+            unsafe fn try_acquire_for(
+                timeout: core::time::Duration,
+            ) -> Option<$crate::RawRestoreState> {
+                let state = I::try_acquire_for(timeout)?;
+                Self::push();
+                Some(state)
+            }
+        }
+    };
+}
+pub(crate) use forward_impl;
+
+/// Critical section implementation that does nothing, for testing wrappers from a single thread.
+#[cfg(test)]
+pub(crate) struct NoopImpl;
+
+#[cfg(test)]
+unsafe impl crate::Impl for NoopImpl {
+    unsafe fn acquire() -> crate::RawRestoreState {
+        crate::RestoreState::invalid().0
+    }
+
+    unsafe fn release(_restore_state: crate::RawRestoreState) {}
+}
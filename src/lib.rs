@@ -1,13 +1,62 @@
 #![cfg_attr(not(feature = "std"), no_std)]
 #![doc = include_str!("../README.md")]
-
+// The README wraps list items without indenting the continuation lines.
+#![allow(unknown_lints, clippy::doc_lazy_continuation)]
+
+pub mod atomic;
+#[cfg(feature = "conformance")]
+pub mod conformance;
+mod cs_mut;
+pub mod impls;
+#[cfg(feature = "mock")]
+pub mod mock;
 mod mutex;
+mod once;
+#[cfg(feature = "std-replay")]
+pub mod replay;
+#[cfg(feature = "sim")]
+pub mod sim;
 #[cfg(feature = "std")]
 mod std;
+#[cfg(feature = "trace-buffer")]
+pub mod trace_buffer;
+#[cfg(feature = "std-watchdog")]
+pub mod watchdog;
+
+#[cfg(all(feature = "tracing", not(feature = "std")))]
+compile_error!("The `tracing` Cargo feature requires the `std` Cargo feature");
+
+#[cfg(all(feature = "mock", feature = "sim"))]
+compile_error!("The `mock` and `sim` Cargo features can't be enabled at the same time");
+
+#[cfg(all(
+    loom,
+    any(
+        feature = "std-signal-mask",
+        feature = "std-watchdog",
+        feature = "std-replay",
+        feature = "mock",
+        feature = "sim",
+        feature = "tracing"
+    )
+))]
+compile_error!(
+    "`--cfg loom` is only supported with the `std` Cargo feature, not the features extending it"
+);
+
+#[cfg(all(feature = "std-signal-mask", not(unix)))]
+compile_error!("The `std-signal-mask` Cargo feature is only supported on Unix targets");
 
 use core::marker::PhantomData;
+#[cfg(feature = "std")]
+use core::panic::Location;
+use core::time::Duration;
 
-pub use self::mutex::Mutex;
+pub use self::cs_mut::{with_mut, CriticalSectionMut};
+pub use self::mutex::{LateInitError, Mutex, MutexMut, MutexTuple};
+pub use self::once::{Lazy, OnceCell};
+#[cfg(feature = "std")]
+pub use self::std::owner;
 
 /// Critical section token.
 ///
@@ -193,11 +242,15 @@ impl RestoreState {
 ///   on a memory location shared by all critical sections, on which the `release` call will do a
 ///   [`core::sync::atomic::Ordering::Release`] operation.
 #[inline(always)]
+#[cfg_attr(feature = "std", track_caller)]
 pub unsafe fn acquire() -> RestoreState {
     extern "Rust" {
         fn _critical_section_1_0_acquire() -> RawRestoreState;
     }
 
+    #[cfg(feature = "std")]
+    crate::std::set_caller(Location::caller());
+
     #[allow(clippy::unit_arg)]
     RestoreState(_critical_section_1_0_acquire())
 }
@@ -210,7 +263,17 @@ pub unsafe fn acquire() -> RestoreState {
 ///
 /// See [`acquire`] for the safety contract description.
 #[inline(always)]
+#[cfg_attr(feature = "std", track_caller)]
 pub unsafe fn release(restore_state: RestoreState) {
+    #[cfg(feature = "std")]
+    crate::std::set_caller(Location::caller());
+
+    release_inner(restore_state)
+}
+
+/// [`release`], without recording the caller for the `std` implementation.
+#[inline(always)]
+unsafe fn release_inner(restore_state: RestoreState) {
     extern "Rust" {
         fn _critical_section_1_0_release(restore_state: RawRestoreState);
     }
@@ -219,6 +282,86 @@ pub unsafe fn release(restore_state: RestoreState) {
     _critical_section_1_0_release(restore_state.0)
 }
 
+/// Try to acquire a critical section in the current thread, without blocking.
+///
+/// This function is extremely low level. Strongly prefer using [`try_with`] instead.
+///
+/// Returns `None` if the critical section is currently held by another thread or core. If it's
+/// already held by the current thread, this succeeds like a nested [`acquire`] does.
+///
+/// The critical section implementation must support this, see [`TryImpl`].
+///
+/// # Safety
+///
+/// If this returns `Some`, the returned restore state is subject to the same safety contract as
+/// the one returned by [`acquire`].
+#[inline(always)]
+#[cfg_attr(feature = "std", track_caller)]
+pub unsafe fn try_acquire() -> Option<RestoreState> {
+    extern "Rust" {
+        fn _critical_section_1_0_try_acquire() -> Option<RawRestoreState>;
+    }
+
+    #[cfg(feature = "std")]
+    crate::std::set_caller(Location::caller());
+
+    _critical_section_1_0_try_acquire().map(RestoreState)
+}
+
+/// Try to acquire a critical section in the current thread, giving up after `timeout`.
+///
+/// This function is extremely low level. Strongly prefer using [`with_timeout`] instead.
+///
+/// Returns `None` if the critical section could not be acquired before the timeout elapsed.
+///
+/// The critical section implementation must support this, see [`TryImpl`].
+///
+/// # Safety
+///
+/// If this returns `Some`, the returned restore state is subject to the same safety contract as
+/// the one returned by [`acquire`].
+#[inline(always)]
+#[cfg_attr(feature = "std", track_caller)]
+pub unsafe fn try_acquire_for(timeout: Duration) -> Option<RestoreState> {
+    extern "Rust" {
+        fn _critical_section_1_0_try_acquire_for(timeout: Duration) -> Option<RawRestoreState>;
+    }
+
+    #[cfg(feature = "std")]
+    crate::std::set_caller(Location::caller());
+
+    _critical_section_1_0_try_acquire_for(timeout).map(RestoreState)
+}
+
+/// Returns whether the critical section is acquired in the current thread.
+///
+/// This is meant for diagnostics, for example to `debug_assert!` that a function is (or is not)
+/// called from within a critical section.
+///
+/// Returns `None` if the critical section implementation can't tell. See [`Impl::is_acquired`].
+#[inline(always)]
+pub fn is_acquired() -> Option<bool> {
+    extern "Rust" {
+        fn _critical_section_1_0_is_acquired() -> Option<bool>;
+    }
+
+    unsafe { _critical_section_1_0_is_acquired() }
+}
+
+/// Returns the critical section nesting depth in the current thread.
+///
+/// This is `Some(0)` outside a critical section, `Some(1)` inside the outermost one, and so on.
+///
+/// Returns `None` if the critical section implementation doesn't track nesting. See [`Impl::depth`].
+#[inline(always)]
+pub fn depth() -> Option<usize> {
+    extern "Rust" {
+        fn _critical_section_1_0_depth() -> Option<usize>;
+    }
+
+    unsafe { _critical_section_1_0_depth() }
+}
+
 /// Execute closure `f` in a critical section.
 ///
 /// Nesting critical sections is allowed. The inner critical sections
@@ -229,23 +372,112 @@ pub unsafe fn release(restore_state: RestoreState) {
 /// This function panics if the given closure `f` panics. In this case
 /// the critical section is released before unwinding.
 #[inline]
+#[cfg_attr(feature = "std", track_caller)]
 pub fn with<R>(f: impl FnOnce(CriticalSection) -> R) -> R {
-    // Helper for making sure `release` is called even if `f` panics.
-    struct Guard {
-        state: RestoreState,
+    // The guard makes sure `release` is called even if `f` panics.
+    let guard = unsafe { CriticalSectionGuard::enter() };
+
+    f(guard.token())
+}
+
+/// Execute closure `f` in a critical section, if it can be acquired without blocking.
+///
+/// Returns `None` without calling `f` if the critical section is currently held by another
+/// thread or core.
+///
+/// The critical section implementation must support this, see [`TryImpl`].
+///
+/// # Panics
+///
+/// This function panics if the given closure `f` panics. In this case
+/// the critical section is released before unwinding.
+#[inline]
+#[cfg_attr(feature = "std", track_caller)]
+pub fn try_with<R>(f: impl FnOnce(CriticalSection) -> R) -> Option<R> {
+    let state = unsafe { try_acquire() }?;
+    let guard = CriticalSectionGuard::from_state(state);
+
+    Some(f(guard.token()))
+}
+
+/// Execute closure `f` in a critical section, waiting at most `timeout` to acquire it.
+///
+/// Returns `None` without calling `f` if the critical section could not be acquired
+/// before the timeout elapsed.
+///
+/// The critical section implementation must support this, see [`TryImpl`].
+///
+/// # Panics
+///
+/// This function panics if the given closure `f` panics. In this case
+/// the critical section is released before unwinding.
+#[inline]
+#[cfg_attr(feature = "std", track_caller)]
+pub fn with_timeout<R>(timeout: Duration, f: impl FnOnce(CriticalSection) -> R) -> Option<R> {
+    let state = unsafe { try_acquire_for(timeout) }?;
+    let guard = CriticalSectionGuard::from_state(state);
+
+    Some(f(guard.token()))
+}
+
+/// Releases the critical section when dropped, including when unwinding from a panic.
+///
+/// This is only used by the closure-based functions such as [`with`]. Handing it out would let
+/// safe code release critical sections out of order, for example by holding guards across
+/// `.await` points in two futures polled in an interleaved way.
+#[derive(Debug)]
+struct CriticalSectionGuard {
+    state: RestoreState,
+
+    // Where the guard was created, so the `std` implementation can attribute the release to it.
+    #[cfg(feature = "std")]
+    location: &'static Location<'static>,
+
+    // Prevent CriticalSectionGuard from being Send or Sync, for the same reasons as CriticalSection.
+    _not_send_sync: PhantomData<*mut ()>,
+}
+
+impl CriticalSectionGuard {
+    /// Acquires the critical section and returns a guard that releases it on drop.
+    ///
+    /// # Safety
+    ///
+    /// The caller must ensure guards are dropped in the reverse order they were created,
+    /// see [`acquire`] for the full safety contract.
+    #[inline(always)]
+    #[cfg_attr(feature = "std", track_caller)]
+    pub(crate) unsafe fn enter() -> Self {
+        Self::from_state(acquire())
     }
 
-    impl Drop for Guard {
-        #[inline(always)]
-        fn drop(&mut self) {
-            unsafe { release(self.state) }
+    #[inline(always)]
+    #[cfg_attr(feature = "std", track_caller)]
+    fn from_state(state: RestoreState) -> Self {
+        CriticalSectionGuard {
+            state,
+            #[cfg(feature = "std")]
+            location: Location::caller(),
+            _not_send_sync: PhantomData,
         }
     }
 
-    let state = unsafe { acquire() };
-    let _guard = Guard { state };
+    /// Returns a critical section token valid for as long as the guard is borrowed.
+    #[inline(always)]
+    pub(crate) fn token(&self) -> CriticalSection<'_> {
+        // SAFETY: the critical section is held until the guard is dropped, which can't
+        // happen while the returned token borrows it.
+        unsafe { CriticalSection::new() }
+    }
+}
+
+impl Drop for CriticalSectionGuard {
+    #[inline(always)]
+    fn drop(&mut self) {
+        #[cfg(feature = "std")]
+        crate::std::set_caller(self.location);
 
-    unsafe { f(CriticalSection::new()) }
+        unsafe { release_inner(self.state) }
+    }
 }
 
 /// Methods required for a critical section implementation.
@@ -269,6 +501,58 @@ pub unsafe trait Impl {
     ///
     /// Callers must uphold the contract specified in [`crate::acquire`] and [`crate::release`].
     unsafe fn release(restore_state: RawRestoreState);
+
+    /// Returns whether the critical section is acquired in the current thread.
+    ///
+    /// Implementations that can tell should override this. The default implementation
+    /// derives it from [`Impl::depth`], returning `None` if that is unknown too.
+    #[inline(always)]
+    fn is_acquired() -> Option<bool> {
+        Self::depth().map(|depth| depth > 0)
+    }
+
+    /// Returns the critical section nesting depth in the current thread.
+    ///
+    /// Implementations that track nesting should override this. The default implementation
+    /// returns `None`.
+    #[inline(always)]
+    fn depth() -> Option<usize> {
+        None
+    }
+}
+
+/// Methods required for a critical section implementation supporting non-blocking acquisition.
+///
+/// This is optional, implementations that can't give up on acquiring the critical section should
+/// not implement it. Using [`try_with`], [`with_timeout`], [`try_acquire`] or [`try_acquire_for`]
+/// with such an implementation fails to link, instead of silently blocking.
+///
+/// Use [`set_try_impl!`] in addition to [`set_impl!`] to register it.
+///
+/// # Safety
+///
+/// Implementations must uphold the contract specified in [`crate::acquire`] and [`crate::release`]
+/// for the restore states they return.
+pub unsafe trait TryImpl: Impl {
+    /// Try to acquire the critical section, without blocking.
+    ///
+    /// Must return `None` only if the critical section is held by another thread or core. If it's
+    /// held by the current thread, this must behave like a nested [`Impl::acquire`].
+    ///
+    /// # Safety
+    ///
+    /// Callers must uphold the contract specified in [`crate::acquire`] and [`crate::release`].
+    unsafe fn try_acquire() -> Option<RawRestoreState>;
+
+    /// Try to acquire the critical section, blocking for at most `timeout`.
+    ///
+    /// Must return `None` only if the critical section was held by another thread or core
+    /// for the whole duration of `timeout`.
+    ///
+    /// # Safety
+    ///
+    /// Callers must uphold the contract specified in [`crate::acquire`] and [`crate::release`].
+    unsafe fn try_acquire_for(timeout: Duration) -> Option<RawRestoreState>;
 }
 
 /// Set the critical section implementation.
@@ -304,5 +588,85 @@ macro_rules! set_impl {
         unsafe fn _critical_section_1_0_release(restore_state: $crate::RawRestoreState) {
             <$t as $crate::Impl>::release(restore_state)
         }
+        #[no_mangle]
+        fn _critical_section_1_0_is_acquired() -> ::core::option::Option<bool> {
+            <$t as $crate::Impl>::is_acquired()
+        }
+        #[no_mangle]
+        fn _critical_section_1_0_depth() -> ::core::option::Option<usize> {
+            <$t as $crate::Impl>::depth()
+        }
     };
 }
+
+/// Set the non-blocking critical section implementation.
+///
+/// This must be used together with [`set_impl!`], with the same type.
+///
+/// # Example
+///
+/// ```
+/// # #[cfg(not(feature = "std"))] // needed for `cargo test --features std`
+/// # mod no_std {
+/// use core::time::Duration;
+/// use critical_section::RawRestoreState;
+///
+/// struct MyCriticalSection;
+/// critical_section::set_impl!(MyCriticalSection);
+/// critical_section::set_try_impl!(MyCriticalSection);
+///
+/// unsafe impl critical_section::Impl for MyCriticalSection {
+///     unsafe fn acquire() -> RawRestoreState {
+///         // ...
+///     }
+///
+///     unsafe fn release(restore_state: RawRestoreState) {
+///         // ...
+///     }
+/// }
+///
+/// unsafe impl critical_section::TryImpl for MyCriticalSection {
+///     unsafe fn try_acquire() -> Option<RawRestoreState> {
+///         // ...
+/// #       Some(())
+///     }
+///
+///     unsafe fn try_acquire_for(timeout: Duration) -> Option<RawRestoreState> {
+///         // ...
+/// #       Some(())
+///     }
+/// }
+/// # }
+#[macro_export]
+macro_rules! set_try_impl {
+    ($t: ty) => {
+        #[no_mangle]
+        unsafe fn _critical_section_1_0_try_acquire(
+        ) -> ::core::option::Option<$crate::RawRestoreState> {
+            <$t as $crate::TryImpl>::try_acquire()
+        }
+        #[no_mangle]
+        unsafe fn _critical_section_1_0_try_acquire_for(
+            timeout: ::core::time::Duration,
+        ) -> ::core::option::Option<$crate::RawRestoreState> {
+            <$t as $crate::TryImpl>::try_acquire_for(timeout)
+        }
+    };
+}
+
+/// ``` compile_fail
+/// use core::cell::Cell;
+/// use critical_section::Mutex;
+///
+/// static M: Mutex<Cell<u32>> = Mutex::new(Cell::new(0));
+///
+/// async fn bad() {
+///     critical_section::with(|cs| async move {
+///         core::future::ready(()).await;
+///         M.borrow(cs).set(1);
+///     })
+///     .await;
+/// }
+/// ```
+#[cfg(doctest)]
+const TokenMustNotBeHeldAcrossAwaitTest: () = ();
@@ -0,0 +1,265 @@
+//! Recording critical section implementation for unit tests.
+//!
+//! With the `mock` Cargo feature, this crate provides a critical section implementation that
+//! behaves like the `std` one, and additionally records every acquire and release done by the
+//! current thread. Tests can then check how the code under test uses critical sections, for
+//! example that it enters exactly one, never nests them, or releases them before calling back
+//! into user code.
+//!
+//! Each [`Event`] records the location of the code that acquired or released the critical
+//! section. For [`with`](crate::with) this is where it was called, for both the acquire and
+//! release events.
+//!
+//! Events are recorded per thread, so tests running in parallel don't see each other's events.
+//!
+//! # Example
+//!
+//! ```
+//! fn increment_twice(counter: &critical_section::Mutex<core::cell::Cell<u32>>) {
+//!     critical_section::with(|cs| {
+//!         let counter = counter.borrow(cs);
+//!         counter.set(counter.get() + 2);
+//!     });
+//! }
+//!
+//! use critical_section::mock;
+//!
+//! let counter = critical_section::Mutex::new(core::cell::Cell::new(0));
+//! mock::reset();
+//! increment_twice(&counter);
+//! mock::assert_acquired(1);
+//! assert_eq!(mock::max_depth(), 1);
+//! ```
+
+use std::cell::{Cell, RefCell};
+use std::fmt;
+use std::panic::Location;
+use std::time::Duration;
+use std::vec::Vec;
+
+use crate::std::StdCriticalSection;
+use crate::{Impl, TryImpl};
+
+/// Kind of an [`Event`].
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum EventKind {
+    /// The critical section was acquired.
+    Acquire,
+    /// The critical section was released.
+    Release,
+}
+
+/// Acquire or release of the critical section, recorded by the mock implementation.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Event {
+    /// Whether the critical section was acquired or released.
+    pub kind: EventKind,
+    /// Nesting depth of the critical section acquired or released, `1` for the outermost one.
+    pub depth: usize,
+    /// Location of the code that acquired or released the critical section.
+    pub location: &'static Location<'static>,
+}
+
+impl fmt::Display for Event {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let kind = match self.kind {
+            EventKind::Acquire => "acquire",
+            EventKind::Release => "release",
+        };
+        write!(f, "{} at {} (depth {})", kind, self.location, self.depth)
+    }
+}
+
+std::thread_local! {
+    static EVENTS: RefCell<Vec<Event>> = const { RefCell::new(Vec::new()) };
+    static DEPTH: Cell<usize> = const { Cell::new(0) };
+    static MAX_DEPTH: Cell<usize> = const { Cell::new(0) };
+}
+
+/// Returns the events recorded in the current thread since the last [`reset`].
+pub fn events() -> Vec<Event> {
+    EVENTS.with(|e| e.borrow().clone())
+}
+
+/// Returns the maximum nesting depth reached in the current thread since the last [`reset`].
+pub fn max_depth() -> usize {
+    MAX_DEPTH.with(|m| m.get())
+}
+
+/// Clears the events and maximum depth recorded in the current thread.
+///
+/// Critical sections currently acquired are not affected, and their releases will be recorded.
+pub fn reset() {
+    EVENTS.with(|e| e.borrow_mut().clear());
+    MAX_DEPTH.with(|m| m.set(DEPTH.with(|d| d.get())));
+}
+
+/// Asserts that the critical section was acquired exactly `n` times in the current thread since
+/// the last [`reset`], counting nested acquisitions.
+///
+/// # Panics
+///
+/// This function panics if the number of acquisitions is not `n`, listing the recorded events.
+#[track_caller]
+pub fn assert_acquired(n: usize) {
+    let events = events();
+    let acquired = events
+        .iter()
+        .filter(|e| e.kind == EventKind::Acquire)
+        .count();
+    if acquired != n {
+        let mut msg = std::format!(
+            "expected the critical section to be acquired {} time(s), but it was acquired {} time(s)",
+            n,
+            acquired
+        );
+        for event in &events {
+            msg.push_str(&std::format!("\n    {}", event));
+        }
+        panic!("{}", msg);
+    }
+}
+
+/// Returns the location of the code calling the implementation.
+///
+/// Must be called before calling the `std` implementation, which consumes it.
+#[track_caller]
+fn caller() -> &'static Location<'static> {
+    // The fallback location is inside `set_impl!`, for code calling the implementation directly.
+    crate::std::caller().unwrap_or_else(Location::caller)
+}
+
+fn record(kind: EventKind, depth: usize, location: &'static Location<'static>) {
+    EVENTS.with(|e| {
+        e.borrow_mut().push(Event {
+            kind,
+            depth,
+            location,
+        })
+    });
+}
+
+fn on_acquire(location: &'static Location<'static>) {
+    let depth = DEPTH.with(|d| d.get()) + 1;
+    DEPTH.with(|d| d.set(depth));
+    MAX_DEPTH.with(|m| m.set(m.get().max(depth)));
+    record(EventKind::Acquire, depth, location);
+}
+
+pub(crate) struct MockCriticalSection;
+crate::set_impl!(MockCriticalSection);
+crate::set_try_impl!(MockCriticalSection);
+
+unsafe impl Impl for MockCriticalSection {
+    #[track_caller]
+    unsafe fn acquire() -> bool {
+        let location = caller();
+        let state = StdCriticalSection::acquire();
+        on_acquire(location);
+        state
+    }
+
+    #[track_caller]
+    unsafe fn release(restore_state: bool) {
+        let depth = DEPTH.with(|d| d.get());
+        if depth == 0 {
+            // Releasing the `std` implementation now would be UB.
+            panic!("`release` called while the critical section is not acquired");
+        }
+        record(EventKind::Release, depth, caller());
+        DEPTH.with(|d| d.set(depth - 1));
+        StdCriticalSection::release(restore_state)
+    }
+
+    fn is_acquired() -> Option<bool> {
+        Some(DEPTH.with(|d| d.get()) > 0)
+    }
+
+    fn depth() -> Option<usize> {
+        Some(DEPTH.with(|d| d.get()))
+    }
+}
+
+unsafe impl TryImpl for MockCriticalSection {
+    #[track_caller]
+    unsafe fn try_acquire() -> Option<bool> {
+        let location = caller();
+        let state = StdCriticalSection::try_acquire()?;
+        on_acquire(location);
+        Some(state)
+    }
+
+    #[track_caller]
+    unsafe fn try_acquire_for(timeout: Duration) -> Option<bool> {
+        let location = caller();
+        let state = StdCriticalSection::try_acquire_for(timeout)?;
+        on_acquire(location);
+        Some(state)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate as critical_section;
+    use critical_section::mock::{self, Event, EventKind};
+
+    fn assert_event(event: Event, kind: EventKind, depth: usize, line: u32) {
+        assert_eq!(event.kind, kind);
+        assert_eq!(event.depth, depth);
+        assert_eq!(event.location.file(), file!());
+        assert_eq!(event.location.line(), line);
+    }
+
+    #[cfg(feature = "conformance")]
+    #[test]
+    fn conformance() {
+        critical_section::conformance::run_all::<super::MockCriticalSection>();
+    }
+
+    #[test]
+    fn with() {
+        mock::reset();
+        let outer = line!() + 1;
+        critical_section::with(|_| {
+            let inner = line!() + 1;
+            critical_section::with(|_| {});
+            assert_event(mock::events()[1], EventKind::Acquire, 2, inner);
+            assert_event(mock::events()[2], EventKind::Release, 2, inner);
+        });
+
+        let events = mock::events();
+        assert_eq!(events.len(), 4);
+        assert_event(events[0], EventKind::Acquire, 1, outer);
+        assert_event(events[3], EventKind::Release, 1, outer);
+        mock::assert_acquired(2);
+        assert_eq!(mock::max_depth(), 2);
+        assert_eq!(critical_section::depth(), Some(0));
+
+        mock::reset();
+        assert_eq!(mock::events(), []);
+        assert_eq!(mock::max_depth(), 0);
+    }
+
+    #[test]
+    fn acquire_release() {
+        mock::reset();
+        unsafe {
+            let line = line!() + 1;
+            let state = critical_section::acquire();
+            critical_section::release(state);
+            assert_event(mock::events()[0], EventKind::Acquire, 1, line);
+            assert_event(mock::events()[1], EventKind::Release, 1, line + 1);
+        }
+    }
+
+    #[test]
+    #[should_panic(
+        expected = "expected the critical section to be acquired 2 time(s), but it \
+                               was acquired 1 time(s)"
+    )]
+    fn assert_acquired() {
+        mock::reset();
+        critical_section::with(|_| {});
+        mock::assert_acquired(2);
+    }
+}
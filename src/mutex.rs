@@ -1,5 +1,8 @@
-use super::CriticalSection;
-use core::cell::{Ref, RefCell, RefMut, UnsafeCell};
+use super::{CriticalSection, CriticalSectionMut};
+use core::cell::{BorrowError, BorrowMutError, Cell, Ref, RefCell, RefMut, UnsafeCell};
+use core::fmt;
+use core::mem;
+use core::mem::size_of;
 
 /// A mutex based on critical sections.
 ///
@@ -41,13 +44,33 @@ use core::cell::{Ref, RefCell, RefMut, UnsafeCell};
 /// a runtime cost that may not be required in all circumstances. For instance,
 /// `Mutex<Cell<T>>` never needs to create `&mut T` or equivalent.
 ///
-/// If `&mut T` is needed, the simplest solution is to use `Mutex<RefCell<T>>`,
+/// If `&mut T` is needed without any runtime cost, use [`MutexMut`] instead. Its data can
+/// only be accessed with the [`CriticalSectionMut`] token provided by
+/// [`with_mut`](crate::with_mut), which can't be copied, and only one of which can exist at a
+/// time.
+///
+/// Otherwise, the simplest solution is to use `Mutex<RefCell<T>>`,
 /// which is the closest analogy to `std::sync::Mutex`. [`RefCell`] inserts the
 /// exact runtime check necessary to guarantee that the `&mut T` reference is
 /// unique.
 ///
-/// To reduce verbosity when using `Mutex<RefCell<T>>`, we reimplement some of
-/// `RefCell`'s methods on it directly.
+/// To reduce verbosity when using `Mutex<Cell<T>>` or `Mutex<RefCell<T>>`, we reimplement
+/// some of `Cell`'s and `RefCell`'s methods on them directly. The `Mutex<Cell<T>>` ones also
+/// have `_in_cs` variants that enter the critical section themselves.
+///
+/// ```no_run
+/// # use critical_section::Mutex;
+/// # use std::cell::Cell;
+///
+/// static COUNTER: Mutex<Cell<u32>> = Mutex::new(Cell::new(0));
+///
+/// fn interrupt_handler() {
+///     // Instead of calling this
+///     critical_section::with(|cs| COUNTER.borrow(cs).set(COUNTER.borrow(cs).get() + 1));
+///     // Call this
+///     COUNTER.update_in_cs(|count| count + 1);
+/// }
+/// ```
 ///
 /// ```no_run
 /// # use critical_section::Mutex;
@@ -116,6 +139,333 @@ impl<T> Mutex<T> {
     }
 }
 
+/// A mutex whose data can only be accessed with the unique critical section token.
+///
+/// Unlike [`Mutex`], this hands out `&mut T` without any runtime borrow tracking. This is sound
+/// because the shared [`CriticalSection`] tokens, of which there can be several, can't access the
+/// data. Only the [`CriticalSectionMut`] token provided by [`with_mut`](crate::with_mut) can, and
+/// only one of those can exist at a time.
+///
+/// # Example
+///
+/// ```
+/// use critical_section::MutexMut;
+///
+/// static SAMPLES: MutexMut<[u16; 4]> = MutexMut::new([0; 4]);
+///
+/// fn record(index: usize, sample: u16) {
+///     critical_section::with_mut(|cs| SAMPLES.borrow_mut(cs)[index] = sample);
+/// }
+/// # #[cfg(not(feature = "std"))] // needed for `cargo test --features std`
+/// # mod no_std {
+/// #     struct MyCriticalSection;
+/// #     critical_section::set_impl!(MyCriticalSection);
+/// #     unsafe impl critical_section::Impl for MyCriticalSection {
+/// #         unsafe fn acquire() -> () {}
+/// #         unsafe fn release(token: ()) {}
+/// #     }
+/// # }
+/// # fn main() { record(1, 42); }
+/// ```
+#[derive(Debug)]
+pub struct MutexMut<T> {
+    // Same as `Mutex::inner`.
+    inner: UnsafeCell<T>,
+}
+
+impl<T> MutexMut<T> {
+    /// Creates a new mutex.
+    #[inline]
+    pub const fn new(value: T) -> Self {
+        MutexMut {
+            inner: UnsafeCell::new(value),
+        }
+    }
+
+    /// Gets a mutable reference to the contained value when the mutex is already uniquely borrowed.
+    ///
+    /// See [`Mutex::get_mut`].
+    #[inline]
+    pub fn get_mut(&mut self) -> &mut T {
+        unsafe { &mut *self.inner.get() }
+    }
+
+    /// Unwraps the contained value, consuming the mutex.
+    #[inline]
+    pub fn into_inner(self) -> T {
+        self.inner.into_inner()
+    }
+
+    /// Borrows the data for as long as the unique critical section token is borrowed.
+    #[inline]
+    pub fn borrow<'cs>(&'cs self, _cs: &'cs CriticalSectionMut<'_>) -> &'cs T {
+        unsafe { &*self.inner.get() }
+    }
+
+    /// Mutably borrows the data for as long as the unique critical section token is borrowed.
+    ///
+    /// Unlike `Mutex<RefCell<T>>`, this has no runtime cost, since [`CriticalSectionMut`]
+    /// guarantees there are no other references to the data.
+    #[inline]
+    pub fn borrow_mut<'cs>(&'cs self, _cs: &'cs mut CriticalSectionMut<'_>) -> &'cs mut T {
+        unsafe { &mut *self.inner.get() }
+    }
+}
+
+impl MutexMut<()> {
+    /// Mutably borrows the data of several mutexes at once, for as long as the unique critical
+    /// section token is borrowed.
+    ///
+    /// `mutexes` is a tuple of 2 to 8 references to mutexes, possibly holding different types.
+    /// This returns a tuple of mutable references to their data, in the same order. Like
+    /// [`MutexMut::borrow_mut`], there is no per-mutex runtime borrow flag.
+    ///
+    /// # Panics
+    ///
+    /// This function panics if any two of the mutexes overlap in memory, for example if the same
+    /// mutex is passed twice.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use critical_section::MutexMut;
+    ///
+    /// static BUFFER: MutexMut<[u8; 16]> = MutexMut::new([0; 16]);
+    /// static LEN: MutexMut<usize> = MutexMut::new(0);
+    ///
+    /// fn push(byte: u8) {
+    ///     critical_section::with_mut(|cs| {
+    ///         let (buffer, len) = MutexMut::borrow_many_mut(cs, (&BUFFER, &LEN));
+    ///         buffer[*len] = byte;
+    ///         *len += 1;
+    ///     });
+    /// }
+    /// # #[cfg(not(feature = "std"))] // needed for `cargo test --features std`
+    /// # mod no_std {
+    /// #     struct MyCriticalSection;
+    /// #     critical_section::set_impl!(MyCriticalSection);
+    /// #     unsafe impl critical_section::Impl for MyCriticalSection {
+    /// #         unsafe fn acquire() -> () {}
+    /// #         unsafe fn release(token: ()) {}
+    /// #     }
+    /// # }
+    /// # fn main() { push(42); }
+    /// ```
+    #[inline]
+    #[track_caller]
+    pub fn borrow_many_mut<'cs, M: MutexTuple<'cs>>(
+        cs: &'cs mut CriticalSectionMut<'_>,
+        mutexes: M,
+    ) -> M::Output {
+        mutexes.borrow_many_mut(cs)
+    }
+}
+
+mod sealed {
+    pub trait Sealed {}
+}
+
+/// Tuple of mutex references that can be borrowed with [`MutexMut::borrow_many_mut`].
+///
+/// This is implemented for tuples of 2 to 8 `&MutexMut<T>`. It is sealed, it can't be implemented
+/// outside of this crate.
+pub trait MutexTuple<'cs>: sealed::Sealed {
+    /// Tuple of mutable references to the data of the mutexes.
+    type Output;
+
+    /// See [`MutexMut::borrow_many_mut`].
+    fn borrow_many_mut(self, cs: &'cs mut CriticalSectionMut<'_>) -> Self::Output;
+}
+
+/// Returns the range of addresses occupied by `mutex`.
+#[inline(always)]
+fn byte_range<T>(mutex: &MutexMut<T>) -> (usize, usize) {
+    let start = mutex as *const MutexMut<T> as usize;
+    (start, start + size_of::<MutexMut<T>>())
+}
+
+#[inline]
+#[track_caller]
+fn check_disjoint(ranges: &[(usize, usize)]) {
+    for (i, a) in ranges.iter().enumerate() {
+        for b in &ranges[i + 1..] {
+            // Zero-sized mutexes never overlap, since their empty ranges fail this check.
+            if a.0 < b.1 && b.0 < a.1 {
+                panic!("`MutexMut::borrow_many_mut` called with overlapping mutexes");
+            }
+        }
+    }
+}
+
+macro_rules! impl_mutex_tuple {
+    ($($name:ident: $t:ident),+) => {
+        impl<'cs, $($t),+> sealed::Sealed for ($(&'cs MutexMut<$t>,)+) {}
+
+        impl<'cs, $($t),+> MutexTuple<'cs> for ($(&'cs MutexMut<$t>,)+) {
+            type Output = ($(&'cs mut $t,)+);
+
+            #[inline]
+            #[track_caller]
+            fn borrow_many_mut(self, _cs: &'cs mut CriticalSectionMut<'_>) -> Self::Output {
+                let ($($name,)+) = self;
+                check_disjoint(&[$(byte_range($name)),+]);
+                // SAFETY: the unique token guarantees no other references to the data exist, and
+                // we just checked the mutexes don't overlap.
+                unsafe { ($(&mut *$name.inner.get(),)+) }
+            }
+        }
+    };
+}
+
+impl_mutex_tuple!(a: A, b: B);
+impl_mutex_tuple!(a: A, b: B, c: C);
+impl_mutex_tuple!(a: A, b: B, c: C, d: D);
+impl_mutex_tuple!(a: A, b: B, c: C, d: D, e: E);
+impl_mutex_tuple!(a: A, b: B, c: C, d: D, e: E, f: F);
+impl_mutex_tuple!(a: A, b: B, c: C, d: D, e: E, f: F, g: G);
+impl_mutex_tuple!(a: A, b: B, c: C, d: D, e: E, f: F, g: G, h: H);
+
+impl<T> Mutex<Cell<T>> {
+    /// Borrow the data and call [`Cell::set`]
+    ///
+    /// This is equivalent to `self.borrow(cs).set(val)`
+    #[inline]
+    pub fn set<'cs>(&'cs self, cs: CriticalSection<'cs>, val: T) {
+        self.borrow(cs).set(val)
+    }
+
+    /// Borrow the data and call [`Cell::replace`]
+    ///
+    /// This is equivalent to `self.borrow(cs).replace(val)`
+    #[inline]
+    pub fn replace<'cs>(&'cs self, cs: CriticalSection<'cs>, val: T) -> T {
+        self.borrow(cs).replace(val)
+    }
+
+    /// Borrow the data of both mutexes and call [`Cell::swap`]
+    ///
+    /// This is equivalent to `self.borrow(cs).swap(other.borrow(cs))`
+    #[inline]
+    pub fn swap<'cs>(&'cs self, cs: CriticalSection<'cs>, other: &'cs Self) {
+        self.borrow(cs).swap(other.borrow(cs))
+    }
+
+    /// Enter a critical section and call [`Cell::set`]
+    ///
+    /// This is equivalent to `critical_section::with(|cs| self.set(cs, val))`
+    #[inline]
+    pub fn set_in_cs(&self, val: T) {
+        crate::with(|cs| self.set(cs, val))
+    }
+
+    /// Enter a critical section and call [`Cell::replace`]
+    ///
+    /// This is equivalent to `critical_section::with(|cs| self.replace(cs, val))`
+    #[inline]
+    pub fn replace_in_cs(&self, val: T) -> T {
+        crate::with(|cs| self.replace(cs, val))
+    }
+
+    /// Enter a critical section and call [`Cell::swap`]
+    ///
+    /// This is equivalent to `critical_section::with(|cs| self.swap(cs, other))`
+    #[inline]
+    pub fn swap_in_cs(&self, other: &Self) {
+        crate::with(|cs| self.swap(cs, other))
+    }
+}
+
+impl<T: Copy> Mutex<Cell<T>> {
+    /// Borrow the data and call [`Cell::get`]
+    ///
+    /// This is equivalent to `self.borrow(cs).get()`
+    #[inline]
+    pub fn get<'cs>(&'cs self, cs: CriticalSection<'cs>) -> T {
+        self.borrow(cs).get()
+    }
+
+    /// Update the contained value using `f`.
+    ///
+    /// This is equivalent to `self.set(cs, f(self.get(cs)))`
+    #[inline]
+    pub fn update<'cs, F>(&'cs self, cs: CriticalSection<'cs>, f: F)
+    where
+        F: FnOnce(T) -> T,
+    {
+        let cell = self.borrow(cs);
+        cell.set(f(cell.get()))
+    }
+
+    /// Fetch the value and apply `f` to it, storing the new value if `f` returns `Some`.
+    ///
+    /// Returns `Ok(previous_value)` if `f` returned `Some`, `Err(previous_value)` otherwise.
+    /// This mirrors the `fetch_update` method of the atomic types, except it never needs to retry
+    /// since no one else can access the value during the critical section.
+    #[inline]
+    pub fn fetch_update<'cs, F>(&'cs self, cs: CriticalSection<'cs>, f: F) -> Result<T, T>
+    where
+        F: FnOnce(T) -> Option<T>,
+    {
+        let cell = self.borrow(cs);
+        let prev = cell.get();
+        match f(prev) {
+            Some(next) => {
+                cell.set(next);
+                Ok(prev)
+            }
+            None => Err(prev),
+        }
+    }
+
+    /// Enter a critical section and call [`Cell::get`]
+    ///
+    /// This is equivalent to `critical_section::with(|cs| self.get(cs))`
+    #[inline]
+    pub fn get_in_cs(&self) -> T {
+        crate::with(|cs| self.get(cs))
+    }
+
+    /// Enter a critical section and update the contained value using `f`.
+    ///
+    /// This is equivalent to `critical_section::with(|cs| self.update(cs, f))`
+    #[inline]
+    pub fn update_in_cs<F>(&self, f: F)
+    where
+        F: FnOnce(T) -> T,
+    {
+        crate::with(|cs| self.update(cs, f))
+    }
+
+    /// Enter a critical section and call [`Mutex::fetch_update`]
+    ///
+    /// This is equivalent to `critical_section::with(|cs| self.fetch_update(cs, f))`
+    #[inline]
+    pub fn fetch_update_in_cs<F>(&self, f: F) -> Result<T, T>
+    where
+        F: FnOnce(T) -> Option<T>,
+    {
+        crate::with(|cs| self.fetch_update(cs, f))
+    }
+}
+
+impl<T: Default> Mutex<Cell<T>> {
+    /// Borrow the data and call [`Cell::take`]
+    ///
+    /// This is equivalent to `self.borrow(cs).take()`
+    #[inline]
+    pub fn take<'cs>(&'cs self, cs: CriticalSection<'cs>) -> T {
+        self.borrow(cs).take()
+    }
+
+    /// Enter a critical section and call [`Cell::take`]
+    ///
+    /// This is equivalent to `critical_section::with(|cs| self.take(cs))`
+    #[inline]
+    pub fn take_in_cs(&self) -> T {
+        crate::with(|cs| self.take(cs))
+    }
+}
+
 impl<T> Mutex<RefCell<T>> {
     /// Borrow the data and call [`RefCell::replace`]
     ///
@@ -175,6 +525,163 @@ impl<T> Mutex<RefCell<T>> {
     pub fn borrow_ref_mut<'cs>(&'cs self, cs: CriticalSection<'cs>) -> RefMut<'cs, T> {
         self.borrow(cs).borrow_mut()
     }
+
+    /// Borrow the data and call [`RefCell::swap`]
+    ///
+    /// This is equivalent to `self.borrow(cs).swap(other.borrow(cs))`
+    ///
+    /// # Panics
+    ///
+    /// This call could panic. See the documentation for [`RefCell::swap`]
+    /// for more details.
+    #[inline]
+    #[track_caller]
+    pub fn swap<'cs>(&'cs self, cs: CriticalSection<'cs>, other: &'cs Self) {
+        self.borrow(cs).swap(other.borrow(cs))
+    }
+
+    /// Borrow the data and map the [`Ref`] with `f`, like [`Ref::map`]
+    ///
+    /// This is equivalent to `Ref::map(self.borrow_ref(cs), f)`
+    ///
+    /// # Panics
+    ///
+    /// This call could panic. See the documentation for [`RefCell::borrow`]
+    /// for more details.
+    #[inline]
+    #[track_caller]
+    pub fn borrow_ref_map<'cs, U: ?Sized, F>(
+        &'cs self,
+        cs: CriticalSection<'cs>,
+        f: F,
+    ) -> Ref<'cs, U>
+    where
+        F: FnOnce(&T) -> &U,
+    {
+        Ref::map(self.borrow_ref(cs), f)
+    }
+
+    /// Mutably borrow the data and map the [`RefMut`] with `f`, like [`RefMut::map`]
+    ///
+    /// This is equivalent to `RefMut::map(self.borrow_ref_mut(cs), f)`
+    ///
+    /// # Panics
+    ///
+    /// This call could panic. See the documentation for [`RefCell::borrow_mut`]
+    /// for more details.
+    #[inline]
+    #[track_caller]
+    pub fn borrow_ref_mut_map<'cs, U: ?Sized, F>(
+        &'cs self,
+        cs: CriticalSection<'cs>,
+        f: F,
+    ) -> RefMut<'cs, U>
+    where
+        F: FnOnce(&mut T) -> &mut U,
+    {
+        RefMut::map(self.borrow_ref_mut(cs), f)
+    }
+
+    /// Borrow the data and call [`RefCell::try_borrow`]
+    ///
+    /// This is equivalent to `self.borrow(cs).try_borrow()`
+    ///
+    /// Returns an error if the data is currently mutably borrowed.
+    #[inline]
+    pub fn try_borrow_ref<'cs>(
+        &'cs self,
+        cs: CriticalSection<'cs>,
+    ) -> Result<Ref<'cs, T>, BorrowError> {
+        self.borrow(cs).try_borrow()
+    }
+
+    /// Borrow the data and call [`RefCell::try_borrow_mut`]
+    ///
+    /// This is equivalent to `self.borrow(cs).try_borrow_mut()`
+    ///
+    /// Returns an error if the data is currently borrowed.
+    #[inline]
+    pub fn try_borrow_ref_mut<'cs>(
+        &'cs self,
+        cs: CriticalSection<'cs>,
+    ) -> Result<RefMut<'cs, T>, BorrowMutError> {
+        self.borrow(cs).try_borrow_mut()
+    }
+
+    /// Fallible version of [`Mutex::replace`]
+    ///
+    /// Returns an error, leaving the data untouched, if it is currently borrowed.
+    #[inline]
+    pub fn try_replace<'cs>(
+        &'cs self,
+        cs: CriticalSection<'cs>,
+        t: T,
+    ) -> Result<T, BorrowMutError> {
+        Ok(mem::replace(&mut *self.try_borrow_ref_mut(cs)?, t))
+    }
+
+    /// Fallible version of [`Mutex::replace_with`]
+    ///
+    /// Returns an error, without calling `f`, if the data is currently borrowed.
+    #[inline]
+    pub fn try_replace_with<'cs, F>(
+        &'cs self,
+        cs: CriticalSection<'cs>,
+        f: F,
+    ) -> Result<T, BorrowMutError>
+    where
+        F: FnOnce(&mut T) -> T,
+    {
+        let mut data = self.try_borrow_ref_mut(cs)?;
+        let replacement = f(&mut data);
+        Ok(mem::replace(&mut *data, replacement))
+    }
+
+    /// Fallible version of [`Mutex::swap`]
+    ///
+    /// Returns an error, leaving both values untouched, if either of them is currently borrowed,
+    /// or if `self` and `other` are the same mutex.
+    #[inline]
+    pub fn try_swap<'cs>(
+        &'cs self,
+        cs: CriticalSection<'cs>,
+        other: &'cs Self,
+    ) -> Result<(), BorrowMutError> {
+        let mut a = self.try_borrow_ref_mut(cs)?;
+        let mut b = other.try_borrow_ref_mut(cs)?;
+        mem::swap(&mut *a, &mut *b);
+        Ok(())
+    }
+
+    /// Fallible version of [`Mutex::borrow_ref_map`]
+    ///
+    /// Returns an error, without calling `f`, if the data is currently mutably borrowed.
+    #[inline]
+    pub fn try_borrow_ref_map<'cs, U: ?Sized, F>(
+        &'cs self,
+        cs: CriticalSection<'cs>,
+        f: F,
+    ) -> Result<Ref<'cs, U>, BorrowError>
+    where
+        F: FnOnce(&T) -> &U,
+    {
+        Ok(Ref::map(self.try_borrow_ref(cs)?, f))
+    }
+
+    /// Fallible version of [`Mutex::borrow_ref_mut_map`]
+    ///
+    /// Returns an error, without calling `f`, if the data is currently borrowed.
+    #[inline]
+    pub fn try_borrow_ref_mut_map<'cs, U: ?Sized, F>(
+        &'cs self,
+        cs: CriticalSection<'cs>,
+        f: F,
+    ) -> Result<RefMut<'cs, U>, BorrowMutError>
+    where
+        F: FnOnce(&mut T) -> &mut U,
+    {
+        Ok(RefMut::map(self.try_borrow_ref_mut(cs)?, f))
+    }
 }
 
 impl<T: Default> Mutex<RefCell<T>> {
@@ -191,6 +698,147 @@ impl<T: Default> Mutex<RefCell<T>> {
     pub fn take<'cs>(&'cs self, cs: CriticalSection<'cs>) -> T {
         self.borrow(cs).take()
     }
+
+    /// Fallible version of [`Mutex::take`]
+    ///
+    /// Returns an error, leaving the data untouched, if it is currently borrowed.
+    #[inline]
+    pub fn try_take<'cs>(&'cs self, cs: CriticalSection<'cs>) -> Result<T, BorrowMutError> {
+        Ok(mem::take(&mut *self.try_borrow_ref_mut(cs)?))
+    }
+}
+
+/// Late-initialization helpers.
+///
+/// `Mutex<RefCell<Option<T>>>` is commonly used for statics that can't be initialized at compile
+/// time, such as peripherals initialized in `main` and then used from interrupt handlers.
+///
+/// ```no_run
+/// # use critical_section::Mutex;
+/// # use std::cell::RefCell;
+/// # #[derive(Debug)]
+/// # struct Uart;
+/// # impl Uart { fn write(&mut self, _: &[u8]) {} }
+///
+/// static UART: Mutex<RefCell<Option<Uart>>> = Mutex::new(RefCell::new(None));
+///
+/// fn main() {
+///     let uart = Uart;
+///     critical_section::with(|cs| UART.init(cs, uart)).unwrap();
+/// }
+///
+/// fn interrupt_handler() {
+///     critical_section::with(|cs| UART.with_inner(cs, |uart| uart.write(b"hello")));
+/// }
+/// ```
+impl<T> Mutex<RefCell<Option<T>>> {
+    /// Initialize the value.
+    ///
+    /// Returns an error together with `value` if the value is already initialized or currently
+    /// borrowed, so it isn't lost.
+    #[inline]
+    pub fn init<'cs>(
+        &'cs self,
+        cs: CriticalSection<'cs>,
+        value: T,
+    ) -> Result<(), (LateInitError, T)> {
+        let mut inner = match self.try_borrow_ref_mut(cs) {
+            Ok(inner) => inner,
+            Err(_) => return Err((LateInitError::Borrowed, value)),
+        };
+        if inner.is_some() {
+            return Err((LateInitError::AlreadyInitialized, value));
+        }
+        *inner = Some(value);
+        Ok(())
+    }
+
+    /// Returns whether the value is initialized.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the value is currently mutably borrowed.
+    #[inline]
+    #[track_caller]
+    pub fn is_initialized<'cs>(&'cs self, cs: CriticalSection<'cs>) -> bool {
+        self.borrow_ref(cs).is_some()
+    }
+
+    /// Call `f` with a mutable reference to the initialized value.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the value is not initialized yet, or if it is currently borrowed (for example
+    /// when calling this from within `f`). See [`Mutex::try_with_inner`] for a non-panicking
+    /// version.
+    #[inline]
+    #[track_caller]
+    pub fn with_inner<'cs, R, F>(&'cs self, cs: CriticalSection<'cs>, f: F) -> R
+    where
+        F: FnOnce(&mut T) -> R,
+    {
+        match self.try_with_inner(cs, f) {
+            Ok(r) => r,
+            Err(e) => panic!("{}", e),
+        }
+    }
+
+    /// Call `f` with a mutable reference to the initialized value.
+    ///
+    /// Returns an error, without calling `f`, if the value is not initialized yet, or if it is
+    /// currently borrowed (for example when calling this from within `f`).
+    #[inline]
+    pub fn try_with_inner<'cs, R, F>(
+        &'cs self,
+        cs: CriticalSection<'cs>,
+        f: F,
+    ) -> Result<R, LateInitError>
+    where
+        F: FnOnce(&mut T) -> R,
+    {
+        let mut inner = self
+            .try_borrow_ref_mut(cs)
+            .map_err(|_| LateInitError::Borrowed)?;
+        match &mut *inner {
+            Some(value) => Ok(f(value)),
+            None => Err(LateInitError::Uninitialized),
+        }
+    }
+
+    /// Take the value out, leaving it uninitialized.
+    ///
+    /// Returns `None` if the value was not initialized.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the value is currently borrowed.
+    #[inline]
+    #[track_caller]
+    pub fn take_inner<'cs>(&'cs self, cs: CriticalSection<'cs>) -> Option<T> {
+        self.borrow_ref_mut(cs).take()
+    }
+}
+
+/// Error returned by the late-initialization helpers of `Mutex<RefCell<Option<T>>>`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum LateInitError {
+    /// The value was used before being initialized.
+    Uninitialized,
+    /// The value was initialized when it was already initialized.
+    AlreadyInitialized,
+    /// The value is currently borrowed, for example by an outer call to
+    /// [`Mutex::with_inner`].
+    Borrowed,
+}
+
+impl fmt::Display for LateInitError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            LateInitError::Uninitialized => "late-initialized value used before initialization",
+            LateInitError::AlreadyInitialized => "late-initialized value already initialized",
+            LateInitError::Borrowed => "late-initialized value already borrowed",
+        })
+    }
 }
 
 // NOTE A `Mutex` can be used as a channel so the protected data must be `Send`
@@ -198,6 +846,9 @@ impl<T: Default> Mutex<RefCell<T>> {
 // threads.
 unsafe impl<T> Sync for Mutex<T> where T: Send {}
 
+// Same as `Mutex`.
+unsafe impl<T> Sync for MutexMut<T> where T: Send {}
+
 /// ``` compile_fail
 /// fn bad(cs: critical_section::CriticalSection) -> &u32 {
 ///     let x = critical_section::Mutex::new(42u32);
@@ -206,3 +857,140 @@ unsafe impl<T> Sync for Mutex<T> where T: Send {}
 /// ```
 #[cfg(doctest)]
 const BorrowMustNotOutliveMutexTest: () = ();
+
+#[cfg(all(test, feature = "std"))]
+mod tests {
+    use crate as critical_section;
+    use critical_section::{LateInitError, Mutex, MutexMut};
+    use std::cell::{Cell, RefCell};
+
+    #[test]
+    fn borrow_many_mut() {
+        static A: MutexMut<u32> = MutexMut::new(1);
+        static B: MutexMut<[u8; 4]> = MutexMut::new([0; 4]);
+        static C: MutexMut<()> = MutexMut::new(());
+        static D: MutexMut<()> = MutexMut::new(());
+
+        critical_section::with_mut(|cs| {
+            let (a, b, _, _) = MutexMut::borrow_many_mut(cs, (&A, &B, &C, &D));
+            b[0] = *a as u8;
+            *a += 1;
+            assert_eq!(*A.borrow(cs), 2);
+            assert_eq!(*B.borrow(cs), [1, 0, 0, 0]);
+        });
+    }
+
+    #[test]
+    fn cell() {
+        static A: Mutex<Cell<u32>> = Mutex::new(Cell::new(1));
+        static B: Mutex<Cell<u32>> = Mutex::new(Cell::new(2));
+
+        critical_section::with(|cs| {
+            assert_eq!(A.get(cs), 1);
+            A.set(cs, 3);
+            assert_eq!(A.replace(cs, 4), 3);
+            A.swap(cs, &B);
+            assert_eq!((A.get(cs), B.get(cs)), (2, 4));
+            A.update(cs, |a| a * 10);
+            assert_eq!(A.fetch_update(cs, |a| Some(a + 1)), Ok(20));
+            assert_eq!(A.fetch_update(cs, |_| None), Err(21));
+            assert_eq!(A.take(cs), 21);
+            assert_eq!(A.get(cs), 0);
+        });
+
+        B.set_in_cs(5);
+        assert_eq!(B.replace_in_cs(6), 5);
+        B.update_in_cs(|b| b + 1);
+        assert_eq!(B.fetch_update_in_cs(|b| b.checked_sub(7)), Ok(7));
+        B.swap_in_cs(&A);
+        assert_eq!(A.get_in_cs(), 0);
+        assert_eq!(B.take_in_cs(), 0);
+    }
+
+    #[test]
+    fn ref_cell() {
+        struct Point {
+            x: u32,
+            y: u32,
+        }
+
+        static A: Mutex<RefCell<Point>> = Mutex::new(RefCell::new(Point { x: 1, y: 2 }));
+        static B: Mutex<RefCell<u32>> = Mutex::new(RefCell::new(3));
+        static C: Mutex<RefCell<u32>> = Mutex::new(RefCell::new(4));
+
+        critical_section::with(|cs| {
+            *A.borrow_ref_mut_map(cs, |p| &mut p.x) += 10;
+            assert_eq!(*A.borrow_ref_map(cs, |p| &p.x), 11);
+
+            {
+                let y = A.try_borrow_ref_mut_map(cs, |p| &mut p.y).unwrap();
+                assert!(A.try_borrow_ref(cs).is_err());
+                assert!(A.try_borrow_ref_map(cs, |p| &p.x).is_err());
+                assert_eq!(*y, 2);
+            }
+
+            {
+                let b = B.try_borrow_ref(cs).unwrap();
+                assert!(B.try_borrow_ref_mut(cs).is_err());
+                assert!(B.try_replace(cs, 5).is_err());
+                assert!(B.try_replace_with(cs, |_| unreachable!()).is_err());
+                assert!(B.try_take(cs).is_err());
+                assert!(B.try_swap(cs, &C).is_err());
+                assert!(C.try_swap(cs, &B).is_err());
+                assert_eq!(*b, 3);
+            }
+
+            assert!(B.try_swap(cs, &B).is_err());
+            assert_eq!(B.try_swap(cs, &C).ok(), Some(()));
+            assert_eq!(B.try_replace(cs, 5).ok(), Some(4));
+            assert_eq!(B.try_replace_with(cs, |b| *b + 1).ok(), Some(5));
+            assert_eq!(B.try_take(cs).ok(), Some(6));
+            B.swap(cs, &C);
+            assert_eq!((*B.borrow_ref(cs), *C.borrow_ref(cs)), (3, 0));
+        });
+    }
+
+    #[test]
+    fn late_init() {
+        static A: Mutex<RefCell<Option<u32>>> = Mutex::new(RefCell::new(None));
+
+        critical_section::with(|cs| {
+            assert!(!A.is_initialized(cs));
+            assert_eq!(
+                A.try_with_inner(cs, |a| *a),
+                Err(LateInitError::Uninitialized)
+            );
+            assert_eq!(A.init(cs, 1), Ok(()));
+            assert_eq!(A.init(cs, 2), Err((LateInitError::AlreadyInitialized, 2)));
+            assert!(A.is_initialized(cs));
+
+            A.with_inner(cs, |a| {
+                *a += 1;
+                assert_eq!(A.try_with_inner(cs, |_| ()), Err(LateInitError::Borrowed));
+                assert_eq!(A.init(cs, 3), Err((LateInitError::Borrowed, 3)));
+            });
+            assert_eq!(A.try_with_inner(cs, |a| *a), Ok(2));
+
+            assert_eq!(A.take_inner(cs), Some(2));
+            assert_eq!(A.take_inner(cs), None);
+        });
+    }
+
+    #[test]
+    #[should_panic(expected = "late-initialized value used before initialization")]
+    fn late_init_uninitialized() {
+        static A: Mutex<RefCell<Option<u32>>> = Mutex::new(RefCell::new(None));
+
+        critical_section::with(|cs| A.with_inner(cs, |_| ()));
+    }
+
+    #[test]
+    #[should_panic(expected = "overlapping mutexes")]
+    fn borrow_many_mut_same_mutex() {
+        static A: MutexMut<u32> = MutexMut::new(0);
+
+        critical_section::with_mut(|cs| {
+            let _ = MutexMut::borrow_many_mut(cs, (&A, &A));
+        });
+    }
+}
@@ -0,0 +1,347 @@
+use core::cell::{Cell, UnsafeCell};
+use core::fmt;
+use core::mem;
+use core::ops::Deref;
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum State {
+    Uninit,
+    Initializing,
+    Init,
+}
+
+/// A cell which can be written to only once, based on critical sections.
+///
+/// This is similar to `std::sync::OnceLock`, but works on all targets supported by
+/// `critical-section`, including ones without atomic compare-and-swap.
+///
+/// The initialization closure passed to [`OnceCell::get_or_init`] runs inside a critical section,
+/// so it should be kept short. If it tries to initialize the same cell again, for example from
+/// an interrupt handler that runs with the critical section nested, it panics instead of
+/// deadlocking or running twice.
+///
+/// # Example
+///
+/// ```
+/// use critical_section::OnceCell;
+///
+/// static CONFIG: OnceCell<u32> = OnceCell::new();
+///
+/// fn config() -> u32 {
+///     *CONFIG.get_or_init(|| 42)
+/// }
+/// # #[cfg(not(feature = "std"))] // needed for `cargo test --features std`
+/// # mod no_std {
+/// #     struct MyCriticalSection;
+/// #     critical_section::set_impl!(MyCriticalSection);
+/// #     unsafe impl critical_section::Impl for MyCriticalSection {
+/// #         unsafe fn acquire() -> () {}
+/// #         unsafe fn release(token: ()) {}
+/// #     }
+/// # }
+/// # fn main() { assert_eq!(config(), 42); }
+/// ```
+pub struct OnceCell<T> {
+    // Only accessed while holding the critical section.
+    state: UnsafeCell<State>,
+    // Only written while holding the critical section and in the `Uninit` state, so
+    // shared references to it can be handed out once it's in the `Init` state.
+    value: UnsafeCell<Option<T>>,
+}
+
+impl<T> OnceCell<T> {
+    /// Creates a new, uninitialized cell.
+    #[inline]
+    pub const fn new() -> Self {
+        OnceCell {
+            state: UnsafeCell::new(State::Uninit),
+            value: UnsafeCell::new(None),
+        }
+    }
+
+    /// Gets a reference to the value, or `None` if the cell is not initialized yet.
+    #[inline]
+    pub fn get(&self) -> Option<&T> {
+        let initialized = crate::with(|_| unsafe { *self.state.get() == State::Init });
+        if initialized {
+            // SAFETY: the value is never written again once initialized.
+            unsafe { (*self.value.get()).as_ref() }
+        } else {
+            None
+        }
+    }
+
+    /// Gets a mutable reference to the value, or `None` if the cell is not initialized yet.
+    ///
+    /// This does not require a critical section since it takes `&mut self`.
+    #[inline]
+    pub fn get_mut(&mut self) -> Option<&mut T> {
+        self.value.get_mut().as_mut()
+    }
+
+    /// Initializes the cell with `value`.
+    ///
+    /// Returns `Err(value)` if the cell is already initialized, or being initialized.
+    #[inline]
+    pub fn set(&self, value: T) -> Result<(), T> {
+        crate::with(|_| unsafe {
+            if *self.state.get() != State::Uninit {
+                return Err(value);
+            }
+            *self.value.get() = Some(value);
+            *self.state.get() = State::Init;
+            Ok(())
+        })
+    }
+
+    /// Gets a reference to the value, initializing it with `f` if the cell is not initialized yet.
+    ///
+    /// `f` runs inside a critical section. If `f` panics, the panic is propagated and the cell
+    /// remains uninitialized.
+    ///
+    /// # Panics
+    ///
+    /// This function panics if `f` (or anything it calls, such as an interrupt handler) tries to
+    /// initialize the cell reentrantly.
+    #[inline]
+    #[track_caller]
+    pub fn get_or_init<F>(&self, f: F) -> &T
+    where
+        F: FnOnce() -> T,
+    {
+        // Helper for going back to `Uninit` if `f` panics.
+        struct InitGuard<'a>(&'a UnsafeCell<State>);
+
+        impl Drop for InitGuard<'_> {
+            #[inline(always)]
+            fn drop(&mut self) {
+                unsafe { *self.0.get() = State::Uninit }
+            }
+        }
+
+        crate::with(|_| unsafe {
+            match *self.state.get() {
+                State::Init => {}
+                State::Initializing => panic!("reentrant initialization of `OnceCell`"),
+                State::Uninit => {
+                    *self.state.get() = State::Initializing;
+                    let guard = InitGuard(&self.state);
+                    let value = f();
+                    mem::forget(guard);
+
+                    *self.value.get() = Some(value);
+                    *self.state.get() = State::Init;
+                }
+            }
+        });
+
+        // SAFETY: the cell is initialized, and the value is never written again.
+        match unsafe { &*self.value.get() } {
+            Some(value) => value,
+            None => unreachable!(),
+        }
+    }
+
+    /// Takes the value out of the cell, leaving it uninitialized.
+    ///
+    /// This does not require a critical section since it takes `&mut self`.
+    #[inline]
+    pub fn take(&mut self) -> Option<T> {
+        *self.state.get_mut() = State::Uninit;
+        self.value.get_mut().take()
+    }
+
+    /// Consumes the cell, returning the value if it was initialized.
+    #[inline]
+    pub fn into_inner(self) -> Option<T> {
+        self.value.into_inner()
+    }
+}
+
+impl<T> Default for OnceCell<T> {
+    #[inline]
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<T: fmt::Debug> fmt::Debug for OnceCell<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.get() {
+            Some(value) => f.debug_tuple("OnceCell").field(value).finish(),
+            None => f.write_str("OnceCell(<uninit>)"),
+        }
+    }
+}
+
+// NOTE `get` hands out `&T` to any thread so `T` must be `Sync`, and `set` can be used to send a
+// value to another thread so `T` must be `Send`.
+unsafe impl<T> Sync for OnceCell<T> where T: Send + Sync {}
+
+/// A value which is initialized on first access, based on critical sections.
+///
+/// This is similar to `std::sync::LazyLock`, and works on all targets supported by
+/// `critical-section`. See [`OnceCell`] for details on how initialization works.
+///
+/// # Example
+///
+/// ```
+/// use critical_section::Lazy;
+///
+/// static TABLE: Lazy<[u32; 4]> = Lazy::new(|| [1, 2, 4, 8]);
+///
+/// fn lookup(i: usize) -> u32 {
+///     TABLE[i]
+/// }
+/// # #[cfg(not(feature = "std"))] // needed for `cargo test --features std`
+/// # mod no_std {
+/// #     struct MyCriticalSection;
+/// #     critical_section::set_impl!(MyCriticalSection);
+/// #     unsafe impl critical_section::Impl for MyCriticalSection {
+/// #         unsafe fn acquire() -> () {}
+/// #         unsafe fn release(token: ()) {}
+/// #     }
+/// # }
+/// # fn main() { assert_eq!(lookup(3), 8); }
+/// ```
+pub struct Lazy<T, F = fn() -> T> {
+    cell: OnceCell<T>,
+    // Only accessed while holding the critical section.
+    init: Cell<Option<F>>,
+}
+
+impl<T, F> Lazy<T, F> {
+    /// Creates a new lazy value with the given initializing function.
+    #[inline]
+    pub const fn new(f: F) -> Self {
+        Lazy {
+            cell: OnceCell::new(),
+            init: Cell::new(Some(f)),
+        }
+    }
+
+    /// Consumes the lazy value, returning the value if it was initialized.
+    #[inline]
+    pub fn into_inner(this: Self) -> Option<T> {
+        this.cell.into_inner()
+    }
+}
+
+impl<T, F: FnOnce() -> T> Lazy<T, F> {
+    /// Forces the evaluation of the lazy value and returns a reference to it.
+    ///
+    /// This is equivalent to dereferencing it.
+    ///
+    /// # Panics
+    ///
+    /// This function panics if the initializing function panicked during a previous evaluation,
+    /// or if it tries to evaluate the lazy value reentrantly.
+    #[inline]
+    #[track_caller]
+    pub fn force(this: &Self) -> &T {
+        this.cell.get_or_init(|| match this.init.take() {
+            Some(f) => f(),
+            None => panic!("`Lazy` instance has previously been poisoned"),
+        })
+    }
+}
+
+impl<T, F: FnOnce() -> T> Deref for Lazy<T, F> {
+    type Target = T;
+
+    #[inline]
+    #[track_caller]
+    fn deref(&self) -> &T {
+        Lazy::force(self)
+    }
+}
+
+impl<T: fmt::Debug, F> fmt::Debug for Lazy<T, F> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.cell.get() {
+            Some(value) => f.debug_tuple("Lazy").field(value).finish(),
+            None => f.write_str("Lazy(<uninit>)"),
+        }
+    }
+}
+
+// NOTE Same bounds as `OnceCell`, plus `F` being `Send` since it may run in any thread.
+unsafe impl<T, F> Sync for Lazy<T, F>
+where
+    T: Send + Sync,
+    F: Send,
+{
+}
+
+#[cfg(all(test, feature = "std"))]
+mod tests {
+    use std::panic;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::thread;
+
+    use super::{Lazy, OnceCell};
+
+    #[test]
+    fn once_cell() {
+        let mut cell = OnceCell::new();
+        assert_eq!(cell.get(), None);
+        assert_eq!(cell.set(1), Ok(()));
+        assert_eq!(cell.set(2), Err(2));
+        assert_eq!(cell.get_or_init(|| unreachable!()), &1);
+        assert_eq!(cell.take(), Some(1));
+        assert_eq!(cell.get_or_init(|| 3), &3);
+        assert_eq!(cell.into_inner(), Some(3));
+    }
+
+    #[test]
+    fn once_cell_init_once() {
+        static CELL: OnceCell<usize> = OnceCell::new();
+        static CALLS: AtomicUsize = AtomicUsize::new(0);
+
+        let threads: Vec<_> = (0..8)
+            .map(|i| {
+                thread::spawn(move || {
+                    *CELL.get_or_init(|| {
+                        CALLS.fetch_add(1, Ordering::Relaxed);
+                        i
+                    })
+                })
+            })
+            .collect();
+        let values: Vec<_> = threads.into_iter().map(|t| t.join().unwrap()).collect();
+
+        assert_eq!(CALLS.load(Ordering::Relaxed), 1);
+        assert!(values.iter().all(|v| *v == values[0]));
+    }
+
+    #[test]
+    #[should_panic(expected = "reentrant initialization of `OnceCell`")]
+    fn once_cell_reentrant() {
+        let cell = OnceCell::new();
+        cell.get_or_init(|| *cell.get_or_init(|| 1) + 1);
+    }
+
+    #[test]
+    fn once_cell_init_panic() {
+        let cell = OnceCell::new();
+        let res = panic::catch_unwind(panic::AssertUnwindSafe(|| {
+            cell.get_or_init(|| panic!("Boom!"));
+        }));
+        assert!(res.is_err());
+        assert_eq!(cell.get(), None);
+        assert_eq!(cell.get_or_init(|| 1), &1);
+    }
+
+    #[test]
+    fn lazy() {
+        static CALLS: AtomicUsize = AtomicUsize::new(0);
+        static LAZY: Lazy<Vec<u32>> = Lazy::new(|| {
+            CALLS.fetch_add(1, Ordering::Relaxed);
+            vec![1, 2, 3]
+        });
+
+        assert_eq!(LAZY.len(), 3);
+        assert_eq!(*Lazy::force(&LAZY), [1, 2, 3]);
+        assert_eq!(CALLS.load(Ordering::Relaxed), 1);
+    }
+}
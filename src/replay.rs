@@ -0,0 +1,315 @@
+//! Recording and replaying the order in which threads acquire the `std` critical section.
+//!
+//! Bugs in multi-threaded code often depend on which thread acquires the critical section
+//! first, which changes from run to run. While [recording](record), each outermost acquisition
+//! appends the acquiring thread to a file. While [replaying](replay) that file, threads wait for
+//! their turn before acquiring the critical section, so they acquire it in the recorded order,
+//! and the bug can be reproduced reliably.
+//!
+//! Threads are identified by their name, or by their [`ThreadId`](std::thread::ThreadId) if they
+//! don't have one. IDs are assigned in the order threads are created, so naming the threads is
+//! more reliable. Threads not matching the [filter](only_threads) are neither recorded nor made
+//! to wait, which is useful to leave out threads whose timing doesn't matter, such as the test
+//! harness.
+//!
+//! While replaying, [`try_with`](crate::try_with) and [`with_timeout`](crate::with_timeout) don't
+//! wait for their turn, and fail if it isn't the current thread's. Once the whole recording is
+//! replayed, threads acquire the critical section in any order. If a thread acquires it more
+//! times than recorded, or waits for its turn for more than 10 seconds because the threads no
+//! longer behave as they did while recording, it panics with the reason.
+//!
+//! This module requires the `std-replay` Cargo feature.
+//!
+//! # Example
+//!
+//! ```no_run
+//! use critical_section::replay;
+//!
+//! replay::only_threads(|thread| matches!(thread.name(), Some(name) if name.starts_with("worker-")));
+//! if std::env::var_os("REPLAY").is_some() {
+//!     replay::replay("acquisitions.log").unwrap();
+//! } else {
+//!     replay::record("acquisitions.log").unwrap();
+//! }
+//!
+//! // Spawn the `worker-*` threads and run the test.
+//!
+//! replay::stop().unwrap();
+//! ```
+
+use std::fs::{self, File};
+use std::io::{self, LineWriter, Write};
+use std::path::Path;
+use std::string::{String, ToString};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Condvar, Mutex, MutexGuard, PoisonError};
+use std::thread::{self, Thread};
+use std::time::{Duration, Instant};
+use std::vec::Vec;
+
+// How long a thread waits for its turn before reporting that the replay diverged.
+const TIMEOUT: Duration = Duration::from_secs(10);
+
+enum Mode {
+    Recording {
+        file: LineWriter<File>,
+        error: Option<io::Error>,
+    },
+    Replaying {
+        order: Vec<String>,
+        next: usize,
+    },
+}
+
+struct Session {
+    mode: Option<Mode>,
+    filter: fn(&Thread) -> bool,
+}
+
+// Fast path for when neither recording nor replaying.
+static ENABLED: AtomicBool = AtomicBool::new(false);
+// Not the critical section's own mutex, so threads can wait for their turn without holding it.
+static SESSION: Mutex<Session> = Mutex::new(Session {
+    mode: None,
+    filter: all_threads,
+});
+static TURN: Condvar = Condvar::new();
+
+fn all_threads(_: &Thread) -> bool {
+    true
+}
+
+fn lock() -> MutexGuard<'static, Session> {
+    // Ignore poison, same as the critical section itself.
+    SESSION.lock().unwrap_or_else(PoisonError::into_inner)
+}
+
+fn key(thread: &Thread) -> String {
+    match thread.name() {
+        Some(name) => name.to_string(),
+        None => std::format!("{:?}", thread.id()),
+    }
+}
+
+fn start(mode: Mode) {
+    let mut session = lock();
+    session.mode = Some(mode);
+    ENABLED.store(true, Ordering::Relaxed);
+    TURN.notify_all();
+}
+
+/// Starts recording the order in which threads acquire the critical section to the file at
+/// `path`, replacing its contents.
+///
+/// This stops any previous recording or replay.
+pub fn record(path: impl AsRef<Path>) -> io::Result<()> {
+    let file = File::create(path)?;
+    start(Mode::Recording {
+        file: LineWriter::new(file),
+        error: None,
+    });
+    Ok(())
+}
+
+/// Starts making threads acquire the critical section in the order recorded in the file at
+/// `path` by [`record`].
+///
+/// This stops any previous recording or replay.
+pub fn replay(path: impl AsRef<Path>) -> io::Result<()> {
+    let order = fs::read_to_string(path)?
+        .lines()
+        .map(ToString::to_string)
+        .collect();
+    start(Mode::Replaying { order, next: 0 });
+    Ok(())
+}
+
+/// Only records or replays the acquisitions of threads for which `filter` returns `true`.
+///
+/// By default, all threads are recorded and replayed.
+pub fn only_threads(filter: fn(&Thread) -> bool) {
+    lock().filter = filter;
+    TURN.notify_all();
+}
+
+/// Stops recording or replaying.
+///
+/// If recording, this returns the first error writing the file, if any.
+pub fn stop() -> io::Result<()> {
+    let mut session = lock();
+    ENABLED.store(false, Ordering::Relaxed);
+    let mode = session.mode.take();
+    TURN.notify_all();
+    match mode {
+        Some(Mode::Recording { mut file, error }) => match error {
+            Some(error) => Err(error),
+            None => file.flush(),
+        },
+        _ => Ok(()),
+    }
+}
+
+/// Must be called right before the current thread acquires the outermost critical section.
+///
+/// Returns whether it's the current thread's turn, which is always the case once this returns if
+/// `block`, or why the replay diverged from the recording, if it did.
+pub(crate) fn wait_turn(block: bool) -> Result<bool, String> {
+    if !ENABLED.load(Ordering::Relaxed) {
+        return Ok(true);
+    }
+    let thread = thread::current();
+    let mut session = lock();
+    let key = key(&thread);
+    let start = Instant::now();
+    loop {
+        if !(session.filter)(&thread) {
+            return Ok(true);
+        }
+        let (order, next) = match &session.mode {
+            Some(Mode::Replaying { order, next }) => (order, *next),
+            _ => return Ok(true),
+        };
+        let expected = match order.get(next) {
+            Some(expected) => expected,
+            // The whole recording was replayed.
+            None => return Ok(true),
+        };
+        if *expected == key {
+            return Ok(true);
+        }
+        if !order[next..].contains(&key) {
+            return Err(std::format!(
+                "critical section replay diverged: thread '{}' acquired it more times than recorded",
+                key
+            ));
+        }
+        if !block {
+            return Ok(false);
+        }
+        let waited = start.elapsed();
+        if waited >= TIMEOUT {
+            return Err(std::format!(
+                "critical section replay diverged: thread '{}' waited {:?} for thread '{}' to \
+                 acquire it (acquisition {} of {})",
+                key,
+                waited,
+                expected,
+                next + 1,
+                order.len()
+            ));
+        }
+        session = TURN
+            .wait_timeout(session, TIMEOUT - waited)
+            .unwrap_or_else(PoisonError::into_inner)
+            .0;
+    }
+}
+
+/// Must be called right after the current thread acquires the outermost critical section.
+pub(crate) fn on_acquire() {
+    if !ENABLED.load(Ordering::Relaxed) {
+        return;
+    }
+    let thread = thread::current();
+    let mut session = lock();
+    if !(session.filter)(&thread) {
+        return;
+    }
+    match &mut session.mode {
+        // Stop writing after the first error, which `stop` returns.
+        Some(Mode::Recording {
+            file,
+            error: error @ None,
+        }) => {
+            if let Err(e) = writeln!(file, "{}", key(&thread)) {
+                *error = Some(e);
+            }
+        }
+        Some(Mode::Replaying { order, next }) if order.get(*next) == Some(&key(&thread)) => {
+            *next += 1;
+            TURN.notify_all();
+        }
+        _ => {}
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::string::String;
+    use std::sync::{Arc, Mutex};
+    use std::thread;
+    use std::time::Duration;
+    use std::vec::Vec;
+
+    use super::{only_threads, record, replay, stop};
+    use crate as critical_section;
+
+    /// Runs three threads acquiring the critical section, and returns the order they acquired it.
+    fn run(delays: [u64; 3]) -> Vec<String> {
+        let order = Arc::new(Mutex::new(Vec::new()));
+        let threads: Vec<_> = delays
+            .iter()
+            .enumerate()
+            .map(|(i, &delay)| {
+                let order = order.clone();
+                thread::Builder::new()
+                    .name(std::format!("replay-{}", i))
+                    .spawn(move || {
+                        for _ in 0..5 {
+                            thread::sleep(Duration::from_millis(delay));
+                            critical_section::with(|_| {
+                                let name = thread::current().name().unwrap().into();
+                                order.lock().unwrap().push(name);
+                            });
+                        }
+                    })
+                    .unwrap()
+            })
+            .collect();
+        for thread in threads {
+            thread.join().unwrap();
+        }
+        let order = order.lock().unwrap().clone();
+        order
+    }
+
+    // A single test, since recording and replaying are global.
+    #[test]
+    fn record_replay() {
+        // Other tests use the critical section concurrently.
+        only_threads(|t| matches!(t.name(), Some(n) if n.starts_with("replay-")));
+        let path = std::env::temp_dir().join(std::format!(
+            "critical-section-replay-{}.log",
+            std::process::id()
+        ));
+
+        record(&path).unwrap();
+        let recorded = run([1, 2, 3]);
+        stop().unwrap();
+        let file = std::fs::read_to_string(&path).unwrap();
+        assert_eq!(file.lines().collect::<Vec<_>>(), recorded);
+
+        // Different timing, same order.
+        replay(&path).unwrap();
+        let replayed = run([3, 0, 1]);
+        stop().unwrap();
+        assert_eq!(replayed, recorded);
+
+        // Trying to acquire the critical section out of turn fails instead of waiting.
+        replay(&path).unwrap();
+        let other = recorded.iter().find(|name| **name != recorded[0]).unwrap();
+        thread::Builder::new()
+            .name(other.clone())
+            .spawn(|| {
+                assert_eq!(critical_section::try_with(|_| ()), None);
+                let timeout = Duration::from_secs(60);
+                assert_eq!(critical_section::with_timeout(timeout, |_| ()), None);
+            })
+            .unwrap()
+            .join()
+            .unwrap();
+        stop().unwrap();
+
+        std::fs::remove_file(&path).unwrap();
+    }
+}
@@ -0,0 +1,380 @@
+//! Simulated interrupts for host-side testing.
+//!
+//! With the `sim` Cargo feature, this crate provides a critical section implementation that
+//! behaves like the `std` one, and additionally simulates an interrupt controller, so the
+//! interaction between a main loop and interrupt handlers can be tested on the host.
+//!
+//! Handlers are registered with [`set_handler`], and triggered with [`pend`]. Like on a
+//! single-core microcontroller, a pended handler runs right away, unless the critical section is
+//! held, in which case it runs when the outermost critical section is released. Handlers don't
+//! preempt each other: interrupts pended while a handler runs are handled after it returns, by
+//! increasing interrupt number.
+//!
+//! Race conditions often only show up when an interrupt happens at a specific point of the main
+//! loop. With [`random_preemption`], interrupts are pended at random preemption points: right
+//! before each outermost critical section is acquired, right after it's released, and at each
+//! call to [`preemption_point`]. The choices only depend on the seed, so a failing test can be
+//! reproduced reliably.
+//!
+//! The simulated interrupt controller is per thread, so tests running in parallel don't interfere
+//! with each other. The critical section is still shared between threads, as with the `std`
+//! implementation.
+//!
+//! # Example
+//!
+//! ```
+//! use core::cell::Cell;
+//! use critical_section::{sim, Mutex};
+//!
+//! static COUNTER: Mutex<Cell<u32>> = Mutex::new(Cell::new(0));
+//!
+//! sim::set_handler(1, || {
+//!     critical_section::with(|cs| COUNTER.borrow(cs).set(COUNTER.borrow(cs).get() + 1))
+//! });
+//!
+//! critical_section::with(|_| {
+//!     sim::pend(1);
+//!     // Deferred until the critical section is released.
+//!     assert!(sim::is_pending(1));
+//! });
+//! assert!(!sim::is_pending(1));
+//! assert_eq!(critical_section::with(|cs| COUNTER.borrow(cs).get()), 1);
+//! ```
+
+use std::boxed::Box;
+use std::cell::RefCell;
+use std::collections::{BTreeMap, BTreeSet};
+use std::time::Duration;
+use std::vec::Vec;
+
+use crate::std::StdCriticalSection;
+use crate::{Impl, TryImpl};
+
+// Deterministic pseudo-random number generator (xorshift64*).
+struct Random {
+    state: u64,
+    irqs: Vec<usize>,
+    one_in: u32,
+}
+
+impl Random {
+    /// Derives the generator state from `seed` with splitmix64, so that close seeds give
+    /// unrelated choices.
+    fn initial_state(seed: u64) -> u64 {
+        let mut z = seed.wrapping_add(0x9e37_79b9_7f4a_7c15);
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+        z ^= z >> 31;
+        // The generator gets stuck at 0.
+        if z == 0 {
+            0x9e37_79b9_7f4a_7c15
+        } else {
+            z
+        }
+    }
+
+    fn next(&mut self) -> u64 {
+        self.state ^= self.state >> 12;
+        self.state ^= self.state << 25;
+        self.state ^= self.state >> 27;
+        self.state.wrapping_mul(0x2545_f491_4f6c_dd1d)
+    }
+
+    /// Returns the interrupt to pend at a preemption point, if any.
+    fn choose(&mut self) -> Option<usize> {
+        if self.irqs.is_empty() || self.next() > u64::MAX / u64::from(self.one_in) {
+            return None;
+        }
+        let i = self.next() % self.irqs.len() as u64;
+        Some(self.irqs[i as usize])
+    }
+}
+
+#[derive(Default)]
+struct Controller {
+    handlers: BTreeMap<usize, Box<dyn FnMut()>>,
+    pending: BTreeSet<usize>,
+    depth: usize,
+    in_handler: bool,
+    random: Option<Random>,
+}
+
+impl Controller {
+    /// Whether pended interrupts can be handled right now.
+    fn can_preempt(&self) -> bool {
+        self.depth == 0 && !self.in_handler
+    }
+}
+
+std::thread_local! {
+    static CONTROLLER: RefCell<Controller> = RefCell::new(Controller::default());
+}
+
+/// Registers `handler` for interrupt `irq` in the current thread, replacing any previous one.
+pub fn set_handler(irq: usize, handler: impl FnMut() + 'static) {
+    CONTROLLER.with(|c| c.borrow_mut().handlers.insert(irq, Box::new(handler)));
+}
+
+/// Pends interrupt `irq` in the current thread.
+///
+/// Its handler runs right away, unless the critical section is held or a handler is running.
+///
+/// # Panics
+///
+/// Running the handler panics if no handler is registered for `irq`.
+pub fn pend(irq: usize) {
+    CONTROLLER.with(|c| c.borrow_mut().pending.insert(irq));
+    dispatch();
+}
+
+/// Returns whether interrupt `irq` is pending in the current thread.
+pub fn is_pending(irq: usize) -> bool {
+    CONTROLLER.with(|c| c.borrow().pending.contains(&irq))
+}
+
+/// Enables random preemption in the current thread.
+///
+/// At each preemption point, one of `irqs` is pended with a probability of `1 / one_in`. The
+/// choices only depend on `seed`.
+///
+/// # Panics
+///
+/// This function panics if `one_in` is 0.
+pub fn random_preemption(seed: u64, irqs: &[usize], one_in: u32) {
+    assert!(one_in != 0, "`one_in` must not be 0");
+    let random = Random {
+        state: Random::initial_state(seed),
+        irqs: irqs.to_vec(),
+        one_in,
+    };
+    CONTROLLER.with(|c| c.borrow_mut().random = Some(random));
+}
+
+/// Marks a point of the code under test where an interrupt may happen with
+/// [`random_preemption`].
+///
+/// This does nothing if the critical section is held or a handler is running.
+pub fn preemption_point() {
+    let irq = CONTROLLER.with(|c| {
+        let mut c = c.borrow_mut();
+        if !c.can_preempt() {
+            return None;
+        }
+        c.random.as_mut()?.choose()
+    });
+    if let Some(irq) = irq {
+        pend(irq);
+    }
+}
+
+/// Removes all handlers, pending interrupts and random preemption of the current thread.
+pub fn reset() {
+    CONTROLLER.with(|c| {
+        let mut c = c.borrow_mut();
+        let depth = c.depth;
+        *c = Controller {
+            depth,
+            ..Controller::default()
+        };
+    });
+}
+
+/// Runs the pending handlers, if possible.
+fn dispatch() {
+    loop {
+        let next = CONTROLLER.with(|c| {
+            let mut c = c.borrow_mut();
+            if !c.can_preempt() {
+                return None;
+            }
+            let irq = *c.pending.iter().next()?;
+            c.pending.remove(&irq);
+            let handler = match c.handlers.remove(&irq) {
+                Some(handler) => handler,
+                None => panic!("interrupt {} pended without a handler", irq),
+            };
+            c.in_handler = true;
+            Some((irq, handler))
+        });
+        let (irq, mut handler) = match next {
+            Some(next) => next,
+            None => return,
+        };
+
+        handler();
+
+        CONTROLLER.with(|c| {
+            let mut c = c.borrow_mut();
+            c.in_handler = false;
+            // Unless the handler registered a new one.
+            c.handlers.entry(irq).or_insert(handler);
+        });
+    }
+}
+
+/// Must be called before acquiring the critical section.
+fn before_acquire() {
+    // Handlers run here set their own caller location, so restore the one of our caller.
+    let caller = crate::std::caller();
+    preemption_point();
+    if let Some(caller) = caller {
+        crate::std::set_caller(caller);
+    }
+}
+
+/// Must be called right after acquiring the critical section.
+fn on_acquire() {
+    CONTROLLER.with(|c| c.borrow_mut().depth += 1);
+}
+
+pub(crate) struct SimCriticalSection;
+crate::set_impl!(SimCriticalSection);
+crate::set_try_impl!(SimCriticalSection);
+
+unsafe impl Impl for SimCriticalSection {
+    unsafe fn acquire() -> bool {
+        before_acquire();
+        let state = StdCriticalSection::acquire();
+        on_acquire();
+        state
+    }
+
+    unsafe fn release(restore_state: bool) {
+        let depth = CONTROLLER.with(|c| c.borrow().depth);
+        if depth == 0 {
+            // Releasing the `std` implementation now would be UB.
+            panic!("`release` called while the critical section is not acquired");
+        }
+        CONTROLLER.with(|c| c.borrow_mut().depth = depth - 1);
+        StdCriticalSection::release(restore_state);
+        if depth == 1 {
+            dispatch();
+            preemption_point();
+        }
+    }
+
+    fn is_acquired() -> Option<bool> {
+        Some(CONTROLLER.with(|c| c.borrow().depth) > 0)
+    }
+
+    fn depth() -> Option<usize> {
+        Some(CONTROLLER.with(|c| c.borrow().depth))
+    }
+}
+
+unsafe impl TryImpl for SimCriticalSection {
+    unsafe fn try_acquire() -> Option<bool> {
+        before_acquire();
+        let state = StdCriticalSection::try_acquire()?;
+        on_acquire();
+        Some(state)
+    }
+
+    unsafe fn try_acquire_for(timeout: Duration) -> Option<bool> {
+        before_acquire();
+        let state = StdCriticalSection::try_acquire_for(timeout)?;
+        on_acquire();
+        Some(state)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::cell::{Cell, RefCell};
+    use std::rc::Rc;
+    use std::vec::Vec;
+
+    use crate as critical_section;
+    use critical_section::sim;
+
+    #[cfg(feature = "conformance")]
+    #[test]
+    fn conformance() {
+        critical_section::conformance::run_all::<super::SimCriticalSection>();
+    }
+
+    #[test]
+    fn deferred_until_outermost_release() {
+        let log = Rc::new(RefCell::new(Vec::new()));
+        for irq in [2, 1] {
+            let log = log.clone();
+            sim::set_handler(irq, move || log.borrow_mut().push(irq));
+        }
+
+        critical_section::with(|_| {
+            critical_section::with(|_| sim::pend(2));
+            sim::pend(1);
+            assert!(sim::is_pending(1) && sim::is_pending(2));
+            assert_eq!(*log.borrow(), []);
+        });
+        // By increasing interrupt number.
+        assert_eq!(*log.borrow(), [1, 2]);
+
+        sim::pend(2);
+        assert_eq!(*log.borrow(), [1, 2, 2]);
+    }
+
+    #[test]
+    fn pend_from_handler() {
+        let log = Rc::new(RefCell::new(Vec::new()));
+        let log1 = log.clone();
+        sim::set_handler(1, move || {
+            sim::pend(2);
+            log1.borrow_mut().push(1);
+        });
+        let log2 = log.clone();
+        sim::set_handler(2, move || log2.borrow_mut().push(2));
+
+        sim::pend(1);
+        assert_eq!(*log.borrow(), [1, 2]);
+    }
+
+    #[test]
+    #[should_panic(expected = "interrupt 3 pended without a handler")]
+    fn no_handler() {
+        sim::pend(3);
+    }
+
+    /// Increments a counter from the main loop and from interrupt 1, and returns how many
+    /// increments were lost.
+    fn lost_increments(seed: u64, racy: bool) -> u32 {
+        sim::reset();
+        let counter = Rc::new(critical_section::Mutex::new(Cell::new(0)));
+        let handled = Rc::new(Cell::new(0));
+        let (c, h) = (counter.clone(), handled.clone());
+        sim::set_handler(1, move || {
+            critical_section::with(|cs| c.borrow(cs).set(c.borrow(cs).get() + 1));
+            h.set(h.get() + 1);
+        });
+        sim::random_preemption(seed, &[1], 4);
+
+        for _ in 0..100 {
+            if racy {
+                let value = critical_section::with(|cs| counter.borrow(cs).get());
+                critical_section::with(|cs| counter.borrow(cs).set(value + 1));
+            } else {
+                critical_section::with(|cs| counter.borrow(cs).set(counter.borrow(cs).get() + 1));
+            }
+        }
+        sim::reset();
+        100 + handled.get() - critical_section::with(|cs| counter.borrow(cs).get())
+    }
+
+    #[test]
+    fn seeds() {
+        // Close seeds must give different generator states.
+        assert_ne!(
+            super::Random::initial_state(2),
+            super::Random::initial_state(3)
+        );
+        assert_ne!(super::Random::initial_state(0), 0);
+    }
+
+    #[test]
+    fn random_preemption() {
+        assert_eq!(lost_increments(42, false), 0);
+        let lost = lost_increments(42, true);
+        assert_ne!(lost, 0);
+        assert_eq!(lost_increments(42, true), lost);
+    }
+}
@@ -1,73 +1,489 @@
 use std::cell::Cell;
 use std::mem::MaybeUninit;
-use std::sync::{Mutex, MutexGuard};
+use std::panic::Location;
+#[cfg(not(loom))]
+use std::ptr::addr_of_mut;
+use std::string::String;
+use std::sync::{PoisonError, TryLockError};
+use std::time::{Duration, Instant};
 
-static GLOBAL_MUTEX: Mutex<()> = Mutex::new(());
+// With `--cfg loom`, everything shared between threads uses the `loom` equivalent, so the
+// implementation can run in `loom::model`.
+#[cfg(loom)]
+use loom::{
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Mutex, MutexGuard,
+    },
+    thread,
+};
+#[cfg(not(loom))]
+use std::{
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Mutex, MutexGuard,
+    },
+    thread,
+};
+
+// Whether anything reports the caller locations. If not, they aren't recorded, to keep
+// thread-local writes off the hot path.
+const RECORD_CALLER: bool = cfg!(any(
+    feature = "std-watchdog",
+    feature = "tracing",
+    feature = "mock",
+    feature = "sim"
+));
+
+// Holds the ID of the thread last recorded in `OWNER`, so a thread acquiring the CS again only
+// updates `OWNER` if another thread held it in between.
+#[cfg(not(loom))]
+static GLOBAL_MUTEX: Mutex<Option<thread::ThreadId>> = Mutex::new(None);
+#[cfg(loom)]
+loom::lazy_static! {
+    static ref GLOBAL_MUTEX: Mutex<Option<thread::ThreadId>> = Mutex::new(None);
+}
+
+type Guard = MutexGuard<'static, Option<thread::ThreadId>>;
 
 // This is initialized if a thread has acquired the CS, uninitialized otherwise.
-static mut GLOBAL_GUARD: MaybeUninit<MutexGuard<'static, ()>> = MaybeUninit::uninit();
+#[cfg(not(loom))]
+static mut GLOBAL_GUARD: MaybeUninit<Guard> = MaybeUninit::uninit();
+#[cfg(loom)]
+loom::lazy_static! {
+    static ref GLOBAL_GUARD: GuardCell = GuardCell(loom::cell::UnsafeCell::new(MaybeUninit::uninit()));
+}
+
+#[cfg(loom)]
+struct GuardCell(loom::cell::UnsafeCell<MaybeUninit<Guard>>);
+
+// SAFETY: only accessed by the thread holding the CS, same as `GLOBAL_GUARD` without loom.
+#[cfg(loom)]
+unsafe impl Sync for GuardCell {}
+
+// Thread that last held the CS, which holds it if `HELD` is set. Not protected by GLOBAL_MUTEX,
+// so other threads can query it while the CS is held.
+#[cfg(not(loom))]
+static OWNER: Mutex<Option<thread::Thread>> = Mutex::new(None);
+#[cfg(not(loom))]
+static HELD: AtomicBool = AtomicBool::new(false);
+#[cfg(loom)]
+loom::lazy_static! {
+    static ref OWNER: Mutex<Option<thread::Thread>> = Mutex::new(None);
+    static ref HELD: AtomicBool = AtomicBool::new(false);
+}
+
+// ID of the current thread, cached since `thread::current()` is comparatively slow.
+#[cfg(not(loom))]
+std::thread_local!(static THREAD_ID: thread::ThreadId = thread::current().id());
+#[cfg(loom)]
+loom::thread_local!(static THREAD_ID: thread::ThreadId = thread::current().id());
+
+// Nesting depth of the CS in the current thread, 0 if it doesn't hold it.
+#[cfg(not(loom))]
+std::thread_local!(static DEPTH: Cell<usize> = const { Cell::new(0) });
+#[cfg(loom)]
+loom::thread_local!(static DEPTH: Cell<usize> = Cell::new(0));
+
+// Location of the code calling `crate::acquire` or `crate::release`, set right before calling the
+// implementation, which consumes it. Always `None` unless `RECORD_CALLER`.
+#[cfg(not(loom))]
+std::thread_local!(static CALLER: Cell<Option<&'static Location<'static>>> = const { Cell::new(None) });
+#[cfg(loom)]
+loom::thread_local!(static CALLER: Cell<Option<&'static Location<'static>>> = Cell::new(None));
 
-std::thread_local!(static IS_LOCKED: Cell<bool> = Cell::new(false));
+// With `std-signal-mask`, this is the signal mask the thread that has acquired the CS had
+// before acquiring it. It's too big to fit in the restore state, so we store it next to
+// GLOBAL_GUARD, which is also only accessed by the thread holding the CS.
+#[cfg(feature = "std-signal-mask")]
+static mut SAVED_SIGNAL_MASK: MaybeUninit<libc::sigset_t> = MaybeUninit::uninit();
 
-struct StdCriticalSection;
+pub(crate) struct StdCriticalSection;
+// With `mock` or `sim`, their implementation wraps this one instead.
+#[cfg(not(any(feature = "mock", feature = "sim")))]
 crate::set_impl!(StdCriticalSection);
+#[cfg(not(any(feature = "mock", feature = "sim")))]
+crate::set_try_impl!(StdCriticalSection);
 
-unsafe impl crate::Impl for StdCriticalSection {
-    unsafe fn acquire() -> bool {
-        // Allow reentrancy by checking thread local state
-        IS_LOCKED.with(|l| {
-            if l.get() {
-                // CS already acquired in the current thread.
-                return true;
+/// Records the location of the code calling the implementation next.
+#[inline(always)]
+pub(crate) fn set_caller(location: &'static Location<'static>) {
+    if RECORD_CALLER {
+        CALLER.with(|c| c.set(Some(location)));
+    }
+}
+
+/// Returns the location of the code calling the implementation, if known.
+#[cfg(any(feature = "mock", feature = "sim"))]
+#[inline(always)]
+pub(crate) fn caller() -> Option<&'static Location<'static>> {
+    CALLER.with(|c| c.get())
+}
+
+/// Acquire the CS, using `lock` to lock `GLOBAL_MUTEX` if the current thread doesn't hold it yet.
+/// While replaying, this waits for the current thread's turn if `block`, and fails if it's not its
+/// turn otherwise.
+///
+/// Returns `None` if `lock` fails, or the restore state otherwise.
+unsafe fn acquire_with(block: bool, lock: impl FnOnce() -> Option<Guard>) -> Option<bool> {
+    // Block signals before doing anything else, so that a signal handler can't enter the CS
+    // while this thread is halfway through acquiring it, or holds it.
+    #[cfg(feature = "std-signal-mask")]
+    let old_mask = signal_mask::block_all();
+
+    // Consume the caller location even when it's not used, so it can't be attributed to a
+    // later call that didn't set it.
+    let location = if RECORD_CALLER {
+        CALLER.with(|c| c.take())
+    } else {
+        None
+    };
+    #[cfg(not(any(feature = "std-watchdog", feature = "tracing")))]
+    let _ = location;
+
+    // Allow reentrancy by checking thread local state
+    DEPTH.with(|d| {
+        if d.get() > 0 {
+            // CS already acquired in the current thread. Signals were already blocked by the
+            // outer acquire, so there's no mask to restore.
+            d.set(d.get() + 1);
+            #[cfg(feature = "tracing")]
+            trace::on_nested_acquire();
+            return Some(true);
+        }
+
+        // Before the wait time measured for tracing, since waiting for our turn is not contention.
+        #[cfg(feature = "std-replay")]
+        match crate::replay::wait_turn(block) {
+            Ok(true) => {}
+            Ok(false) => {
+                #[cfg(feature = "std-signal-mask")]
+                signal_mask::restore(&old_mask);
+                return None;
+            }
+            Err(msg) => {
+                #[cfg(feature = "std-signal-mask")]
+                signal_mask::restore(&old_mask);
+                panic!("{}", msg);
             }
+        }
+        #[cfg(not(feature = "std-replay"))]
+        let _ = block;
 
-            // Note: it is fine to set this flag *before* acquiring the mutex because it's thread local.
-            // No other thread can see its value, there's no potential for races.
-            // This way, we hold the mutex for slightly less time.
-            l.set(true);
-
-            // Not acquired in the current thread, acquire it.
-            let guard = match GLOBAL_MUTEX.lock() {
-                Ok(guard) => guard,
-                Err(err) => {
-                    // Ignore poison on the global mutex in case a panic occurred
-                    // while the mutex was held.
-                    err.into_inner()
-                }
-            };
-            GLOBAL_GUARD.write(guard);
+        #[cfg(feature = "tracing")]
+        let wait_start = std::time::Instant::now();
 
-            false
-        })
+        // Note: it is fine to set the depth *before* acquiring the mutex because it's thread local.
+        // No other thread can see its value, there's no potential for races.
+        // This way, we hold the mutex for slightly less time.
+        d.set(1);
+
+        // Not acquired in the current thread, acquire it.
+        let mut guard = match lock() {
+            Some(guard) => guard,
+            None => {
+                d.set(0);
+                #[cfg(feature = "std-signal-mask")]
+                signal_mask::restore(&old_mask);
+                return None;
+            }
+        };
+        let id = THREAD_ID.with(|id| *id);
+        if *guard != Some(id) {
+            *lock_owner() = Some(thread::current());
+            *guard = Some(id);
+        }
+        HELD.store(true, Ordering::Relaxed);
+        set_guard(guard);
+        #[cfg(feature = "std-replay")]
+        crate::replay::on_acquire();
+        #[cfg(feature = "std-signal-mask")]
+        (*addr_of_mut!(SAVED_SIGNAL_MASK)).write(old_mask);
+        #[cfg(feature = "std-watchdog")]
+        crate::watchdog::on_acquire(location);
+        #[cfg(feature = "tracing")]
+        trace::on_acquire(wait_start.elapsed(), location);
+
+        Some(false)
+    })
+}
+
+/// Stores the guard of the current thread, which has just acquired the CS.
+unsafe fn set_guard(guard: Guard) {
+    #[cfg(not(loom))]
+    (*addr_of_mut!(GLOBAL_GUARD)).write(guard);
+    #[cfg(loom)]
+    GLOBAL_GUARD.0.with_mut(|g| (*g).write(guard));
+}
+
+/// Takes the guard stored by [`set_guard`], leaving `GLOBAL_GUARD` uninitialized.
+///
+/// The current thread must hold the CS.
+unsafe fn take_guard() -> Guard {
+    #[cfg(not(loom))]
+    return (*addr_of_mut!(GLOBAL_GUARD)).assume_init_read();
+    #[cfg(loom)]
+    return GLOBAL_GUARD.0.with_mut(|g| (*g).assume_init_read());
+}
+
+fn lock_owner() -> MutexGuard<'static, Option<thread::Thread>> {
+    // Ignore poison, same as in `lock`.
+    OWNER.lock().unwrap_or_else(PoisonError::into_inner)
+}
+
+/// Returns the thread holding the critical section, if any.
+///
+/// The result may already be outdated when it's returned, unless it's the current thread.
+///
+/// This function requires the `std` Cargo feature.
+pub fn owner() -> Option<thread::Thread> {
+    let owner = lock_owner();
+    if HELD.load(Ordering::Relaxed) {
+        owner.clone()
+    } else {
+        None
+    }
+}
+
+fn describe(thread: &thread::Thread) -> String {
+    match thread.name() {
+        Some(name) => std::format!("thread '{}' ({:?})", name, thread.id()),
+        None => std::format!("thread {:?}", thread.id()),
+    }
+}
+
+/// Panics if the current thread can't release the critical section with `nested_cs`, instead of
+/// causing UB.
+fn check_release(depth: usize, nested_cs: bool) {
+    if depth == 0 {
+        let owner = match owner() {
+            Some(owner) => std::format!("held by {}", describe(&owner)),
+            None => "not held".into(),
+        };
+        panic!(
+            "critical section released by {} which doesn't hold it ({})",
+            describe(&thread::current()),
+            owner
+        );
+    }
+    if nested_cs != (depth > 1) {
+        panic!(
+            "critical section released out of order: the restore state is for {} critical \
+             section, but the current thread holds it at depth {}",
+            if nested_cs {
+                "a nested"
+            } else {
+                "the outermost"
+            },
+            depth
+        );
+    }
+}
+
+fn lock() -> Guard {
+    // Ignore poison on the global mutex in case a panic occurred
+    // while the mutex was held.
+    GLOBAL_MUTEX.lock().unwrap_or_else(PoisonError::into_inner)
+}
+
+fn try_lock() -> Option<Guard> {
+    match GLOBAL_MUTEX.try_lock() {
+        Ok(guard) => Some(guard),
+        // Ignore poison, same as in `lock`.
+        Err(TryLockError::Poisoned(err)) => Some(err.into_inner()),
+        Err(TryLockError::WouldBlock) => None,
+    }
+}
+
+unsafe impl crate::Impl for StdCriticalSection {
+    unsafe fn acquire() -> bool {
+        match acquire_with(true, || Some(lock())) {
+            Some(nested_cs) => nested_cs,
+            None => unreachable!(),
+        }
     }
 
     unsafe fn release(nested_cs: bool) {
-        if !nested_cs {
-            // SAFETY: As per the acquire/release safety contract, release can only be called
-            // if the critical section is acquired in the current thread,
-            // in which case we know the GLOBAL_GUARD is initialized.
+        let depth = DEPTH.with(|d| d.get());
+        check_release(depth, nested_cs);
+
+        if RECORD_CALLER {
+            CALLER.with(|c| c.set(None));
+        }
+
+        #[cfg(feature = "tracing")]
+        trace::on_release(nested_cs);
+
+        if nested_cs {
+            DEPTH.with(|d| d.set(depth - 1));
+        } else {
+            #[cfg(feature = "std-watchdog")]
+            crate::watchdog::on_release();
+            HELD.store(false, Ordering::Relaxed);
+
+            // SAFETY: `check_release` ensures the critical section is acquired in the current
+            // thread, in which case we know the GLOBAL_GUARD is initialized.
             //
             // We have to `assume_init_read` then drop instead of `assume_init_drop` because:
             // - drop requires exclusive access (&mut) to the contents
             // - mutex guard drop first unlocks the mutex, then returns. In between those, there's a brief
             //   moment where the mutex is unlocked but a `&mut` to the contents exists.
             // - During this moment, another thread can go and use GLOBAL_GUARD, causing `&mut` aliasing.
+            //
+            // Same for SAVED_SIGNAL_MASK, we read it before unlocking the mutex.
+            #[cfg(feature = "std-signal-mask")]
+            let old_mask = (*addr_of_mut!(SAVED_SIGNAL_MASK)).assume_init_read();
             #[allow(let_underscore_lock)]
-            let _ = GLOBAL_GUARD.assume_init_read();
+            let _ = take_guard();
 
-            // Note: it is fine to clear this flag *after* releasing the mutex because it's thread local.
+            // Note: it is fine to clear the depth *after* releasing the mutex because it's thread local.
             // No other thread can see its value, there's no potential for races.
             // This way, we hold the mutex for slightly less time.
-            IS_LOCKED.with(|l| l.set(false));
+            DEPTH.with(|d| d.set(0));
+
+            // Unblock signals last, so that a signal handler entering the CS sees it as not acquired
+            // by the current thread.
+            #[cfg(feature = "std-signal-mask")]
+            signal_mask::restore(&old_mask);
         }
     }
+
+    fn is_acquired() -> Option<bool> {
+        Some(DEPTH.with(|d| d.get()) > 0)
+    }
+
+    fn depth() -> Option<usize> {
+        Some(DEPTH.with(|d| d.get()))
+    }
 }
 
-#[cfg(test)]
+unsafe impl crate::TryImpl for StdCriticalSection {
+    unsafe fn try_acquire() -> Option<bool> {
+        acquire_with(false, try_lock)
+    }
+
+    unsafe fn try_acquire_for(timeout: Duration) -> Option<bool> {
+        acquire_with(false, || {
+            // `std::sync::Mutex` has no timed lock, so poll it until the deadline.
+            let deadline = match Instant::now().checked_add(timeout) {
+                Some(deadline) => deadline,
+                // The deadline is too far in the future to be represented, wait forever.
+                None => return Some(lock()),
+            };
+            loop {
+                if let Some(guard) = try_lock() {
+                    return Some(guard);
+                }
+                if Instant::now() >= deadline {
+                    return None;
+                }
+                thread::yield_now();
+            }
+        })
+    }
+}
+
+/// `tracing` spans covering the outermost critical sections.
+#[cfg(feature = "tracing")]
+mod trace {
+    use std::cell::{Cell, RefCell};
+    use std::panic::Location;
+    use std::time::{Duration, Instant};
+
+    use tracing::field::Empty;
+    use tracing::span::EnteredSpan;
+
+    struct Outermost {
+        span: EnteredSpan,
+        acquired_at: Instant,
+    }
+
+    std::thread_local! {
+        static OUTERMOST: RefCell<Option<Outermost>> = const { RefCell::new(None) };
+        // Current and maximum nesting depth of the outermost critical section.
+        static DEPTH: Cell<usize> = const { Cell::new(0) };
+        static MAX_DEPTH: Cell<usize> = const { Cell::new(0) };
+    }
+
+    /// Must be called right after acquiring the outermost critical section, which took `wait`.
+    pub(super) fn on_acquire(wait: Duration, location: Option<&'static Location<'static>>) {
+        DEPTH.with(|d| d.set(1));
+        MAX_DEPTH.with(|d| d.set(1));
+
+        let span = tracing::trace_span!(
+            "critical_section",
+            wait_ns = wait.as_nanos() as u64,
+            hold_ns = Empty,
+            depth = Empty,
+            code.filepath = location.map(|l| l.file()),
+            code.lineno = location.map(|l| l.line()),
+        );
+        let outermost = Outermost {
+            span: span.entered(),
+            acquired_at: Instant::now(),
+        };
+        // Not holding the borrow while entering the span, in case the subscriber uses
+        // critical sections.
+        OUTERMOST.with(|o| *o.borrow_mut() = Some(outermost));
+    }
+
+    /// Must be called right after acquiring a nested critical section.
+    pub(super) fn on_nested_acquire() {
+        let depth = DEPTH.with(|d| d.get()) + 1;
+        DEPTH.with(|d| d.set(depth));
+        MAX_DEPTH.with(|d| d.set(d.get().max(depth)));
+    }
+
+    /// Must be called right before releasing any critical section.
+    pub(super) fn on_release(nested: bool) {
+        DEPTH.with(|d| d.set(d.get().saturating_sub(1)));
+        if nested {
+            return;
+        }
+
+        if let Some(outermost) = OUTERMOST.with(|o| o.borrow_mut().take()) {
+            let hold = outermost.acquired_at.elapsed();
+            outermost.span.record("hold_ns", hold.as_nanos() as u64);
+            outermost
+                .span
+                .record("depth", MAX_DEPTH.with(|d| d.get()) as u64);
+            // Dropping the span exits it.
+        }
+    }
+}
+
+#[cfg(feature = "std-signal-mask")]
+mod signal_mask {
+    use std::mem::MaybeUninit;
+    use std::ptr;
+
+    /// Blocks all signals in the current thread, returning the previous signal mask.
+    pub(super) unsafe fn block_all() -> libc::sigset_t {
+        let mut all = MaybeUninit::uninit();
+        libc::sigfillset(all.as_mut_ptr());
+        let mut old = MaybeUninit::uninit();
+        let res = libc::pthread_sigmask(libc::SIG_BLOCK, all.as_ptr(), old.as_mut_ptr());
+        assert_eq!(res, 0, "pthread_sigmask failed");
+        old.assume_init()
+    }
+
+    /// Sets the signal mask of the current thread.
+    pub(super) unsafe fn restore(mask: &libc::sigset_t) {
+        let res = libc::pthread_sigmask(libc::SIG_SETMASK, mask, ptr::null_mut());
+        assert_eq!(res, 0, "pthread_sigmask failed");
+    }
+}
+
+#[cfg(all(test, not(loom)))]
 mod tests {
+    use std::string::String;
+    use std::sync::mpsc;
     use std::thread;
+    use std::time::Duration;
 
+    use super::StdCriticalSection;
     use crate as critical_section;
+    use crate::Impl;
 
     #[cfg(feature = "std")]
     #[test]
@@ -84,4 +500,304 @@ mod tests {
             panic!("Not a PoisonError!");
         })
     }
+
+    #[cfg(feature = "conformance")]
+    #[test]
+    fn conformance() {
+        critical_section::conformance::run_all::<super::StdCriticalSection>();
+    }
+
+    #[test]
+    fn is_acquired() {
+        assert_eq!(critical_section::is_acquired(), Some(false));
+        critical_section::with(|_| {
+            assert_eq!(critical_section::is_acquired(), Some(true));
+            critical_section::with(|_| {
+                assert_eq!(critical_section::is_acquired(), Some(true));
+            });
+            assert_eq!(critical_section::is_acquired(), Some(true));
+        });
+        assert_eq!(critical_section::is_acquired(), Some(false));
+    }
+
+    #[test]
+    fn depth_and_owner() {
+        assert_eq!(critical_section::depth(), Some(0));
+        critical_section::with(|_| {
+            assert_eq!(critical_section::depth(), Some(1));
+            critical_section::with(|_| {
+                assert_eq!(critical_section::depth(), Some(2));
+            });
+            let owner = critical_section::owner().unwrap();
+            assert_eq!(owner.id(), thread::current().id());
+        });
+        assert_eq!(critical_section::depth(), Some(0));
+    }
+
+    #[test]
+    fn owner_after_other_thread() {
+        critical_section::with(|_| {});
+        thread::spawn(|| critical_section::with(|_| {}))
+            .join()
+            .unwrap();
+        // The owner recorded by the other thread must be replaced, even though the current thread
+        // held the critical section before it.
+        critical_section::with(|_| {
+            let owner = critical_section::owner().unwrap();
+            assert_eq!(owner.id(), thread::current().id());
+        });
+    }
+
+    fn panic_message(f: impl FnOnce() + std::panic::UnwindSafe) -> String {
+        let err = std::panic::catch_unwind(f).unwrap_err();
+        err.downcast_ref::<String>().unwrap().clone()
+    }
+
+    #[test]
+    fn release_on_wrong_thread() {
+        let (acquired_tx, acquired_rx) = mpsc::channel();
+        let (done_tx, done_rx) = mpsc::channel::<()>();
+        let holder = thread::Builder::new()
+            .name("holder".into())
+            .spawn(move || {
+                critical_section::with(|_| {
+                    acquired_tx.send(()).unwrap();
+                    done_rx.recv().unwrap();
+                })
+            })
+            .unwrap();
+        acquired_rx.recv().unwrap();
+        let owner = critical_section::owner().unwrap();
+        assert_eq!(owner.name(), Some("holder"));
+
+        let msg = panic_message(|| unsafe { StdCriticalSection::release(false) });
+        assert!(
+            msg.contains("which doesn't hold it (held by thread 'holder'"),
+            "{}",
+            msg
+        );
+
+        done_tx.send(()).unwrap();
+        holder.join().unwrap();
+    }
+
+    #[test]
+    fn release_out_of_order() {
+        unsafe {
+            let outer = StdCriticalSection::acquire();
+            let inner = StdCriticalSection::acquire();
+            let msg = panic_message(|| StdCriticalSection::release(outer));
+            assert!(
+                msg.starts_with("critical section released out of order"),
+                "{}",
+                msg
+            );
+            StdCriticalSection::release(inner);
+            StdCriticalSection::release(outer);
+        }
+        assert_eq!(critical_section::is_acquired(), Some(false));
+    }
+
+    #[test]
+    fn try_with() {
+        // Other tests may hold the CS for a short time, so don't use `try_with` here.
+        assert_eq!(
+            critical_section::with_timeout(Duration::from_secs(10), |_| 42),
+            Some(42)
+        );
+
+        critical_section::with(|_| {
+            // Nested acquisition in the same thread never fails.
+            assert_eq!(critical_section::try_with(|_| 42), Some(42));
+
+            let (tx, rx) = mpsc::channel();
+            thread::spawn(move || {
+                tx.send(critical_section::try_with(|_| ())).unwrap();
+                tx.send(critical_section::with_timeout(
+                    Duration::from_millis(10),
+                    |_| (),
+                ))
+                .unwrap();
+            });
+            assert_eq!(rx.recv().unwrap(), None);
+            assert_eq!(rx.recv().unwrap(), None);
+        });
+    }
+
+    #[cfg(feature = "tracing")]
+    #[test]
+    fn tracing_span() {
+        use std::string::{String, ToString};
+        use std::sync::{Arc, Mutex};
+        use std::vec::Vec;
+        use tracing::field::{Field, Visit};
+        use tracing::span::{Attributes, Id, Record};
+        use tracing::{Event, Metadata, Subscriber};
+
+        #[derive(Default)]
+        struct Fields(Vec<(&'static str, String)>);
+
+        // Records the name and fields of every span.
+        #[derive(Clone, Default)]
+        struct Recorder(Arc<Mutex<Vec<(&'static str, Fields)>>>);
+
+        impl Visit for Fields {
+            fn record_debug(&mut self, field: &Field, value: &dyn std::fmt::Debug) {
+                self.0.push((field.name(), format!("{:?}", value)));
+            }
+        }
+
+        impl Subscriber for Recorder {
+            fn enabled(&self, _: &Metadata<'_>) -> bool {
+                true
+            }
+            fn new_span(&self, span: &Attributes<'_>) -> Id {
+                let mut spans = self.0.lock().unwrap();
+                let mut fields = Fields::default();
+                span.record(&mut fields);
+                spans.push((span.metadata().name(), fields));
+                Id::from_u64(spans.len() as u64)
+            }
+            fn record(&self, span: &Id, values: &Record<'_>) {
+                let mut spans = self.0.lock().unwrap();
+                values.record(&mut spans[span.into_u64() as usize - 1].1);
+            }
+            fn record_follows_from(&self, _: &Id, _: &Id) {}
+            fn event(&self, _: &Event<'_>) {}
+            fn enter(&self, _: &Id) {}
+            fn exit(&self, _: &Id) {}
+        }
+
+        let recorder = Recorder::default();
+        let line = line!() + 2;
+        tracing::subscriber::with_default(recorder.clone(), || {
+            critical_section::with(|_| {
+                critical_section::with(|_| thread::sleep(Duration::from_millis(1)))
+            });
+        });
+
+        let spans = recorder.0.lock().unwrap();
+        assert_eq!(spans.len(), 1);
+        let (name, fields) = &spans[0];
+        assert_eq!(*name, "critical_section");
+        let field = |name| {
+            let (_, value) = fields.0.iter().find(|(n, _)| *n == name).unwrap();
+            value.clone()
+        };
+        assert_eq!(field("depth"), "2");
+        assert_eq!(field("code.lineno"), line.to_string());
+        assert!(field("wait_ns").parse::<u64>().is_ok());
+        assert!(field("hold_ns").parse::<u64>().unwrap() >= 1_000_000);
+    }
+
+    #[cfg(feature = "std-signal-mask")]
+    #[test]
+    fn signal_handler_in_critical_section() {
+        use std::sync::atomic::{AtomicUsize, Ordering};
+
+        static HANDLED: AtomicUsize = AtomicUsize::new(0);
+
+        extern "C" fn handler(_: libc::c_int) {
+            critical_section::with(|_| {
+                HANDLED.fetch_add(1, Ordering::Relaxed);
+            });
+        }
+
+        unsafe {
+            libc::signal(libc::SIGUSR1, handler as *const () as libc::sighandler_t);
+
+            critical_section::with(|_| {
+                // The signal stays pending until the critical section is released, instead of
+                // deadlocking trying to acquire it again from the handler.
+                libc::pthread_kill(libc::pthread_self(), libc::SIGUSR1);
+                assert_eq!(HANDLED.load(Ordering::Relaxed), 0);
+            });
+        }
+
+        assert_eq!(HANDLED.load(Ordering::Relaxed), 1);
+    }
+}
+
+#[cfg(all(test, loom))]
+mod loom_tests {
+    use std::cell::Cell;
+    use std::vec::Vec;
+
+    use loom::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+    use loom::sync::Arc;
+    use loom::thread;
+
+    use crate as critical_section;
+    use critical_section::Mutex;
+
+    #[test]
+    fn mutual_exclusion() {
+        loom::model(|| {
+            let counter = Arc::new(AtomicUsize::new(0));
+            let threads: Vec<_> = (0..2)
+                .map(|_| {
+                    let counter = counter.clone();
+                    thread::spawn(move || {
+                        critical_section::with(|_| {
+                            // Not atomic, so increments could be lost without the CS.
+                            let value = counter.load(Ordering::Relaxed);
+                            counter.store(value + 1, Ordering::Relaxed);
+                        })
+                    })
+                })
+                .collect();
+            for thread in threads {
+                thread.join().unwrap();
+            }
+            assert_eq!(counter.load(Ordering::Relaxed), 2);
+        });
+    }
+
+    #[test]
+    fn nested() {
+        loom::model(|| {
+            let thread = thread::spawn(|| critical_section::with(|_| {}));
+            critical_section::with(|_| {
+                critical_section::with(|_| {
+                    assert_eq!(critical_section::is_acquired(), Some(true));
+                });
+                assert_eq!(critical_section::is_acquired(), Some(true));
+            });
+            assert_eq!(critical_section::is_acquired(), Some(false));
+            thread.join().unwrap();
+        });
+    }
+
+    #[test]
+    fn try_with() {
+        loom::model(|| {
+            let held = Arc::new(AtomicBool::new(false));
+            let held2 = held.clone();
+            let thread = thread::spawn(move || {
+                critical_section::with(|_| held2.store(true, Ordering::Relaxed));
+            });
+            // Fails at most while the other thread holds the CS.
+            let entered = critical_section::try_with(|_| ()).is_some();
+            thread.join().unwrap();
+            assert!(entered || held.load(Ordering::Relaxed));
+            assert_eq!(critical_section::try_with(|_| 42), Some(42));
+        });
+    }
+
+    #[test]
+    fn flag_and_data() {
+        loom::model(|| {
+            let data = Arc::new(Mutex::new(Cell::new(0)));
+            let ready = Arc::new(AtomicBool::new(false));
+            let (data2, ready2) = (data.clone(), ready.clone());
+            let thread = thread::spawn(move || {
+                critical_section::with(|cs| data2.borrow(cs).set(42));
+                ready2.store(true, Ordering::Release);
+            });
+            if ready.load(Ordering::Acquire) {
+                assert_eq!(critical_section::with(|cs| data.borrow(cs).get()), 42);
+            }
+            thread.join().unwrap();
+        });
+    }
 }
@@ -0,0 +1,408 @@
+//! Ring buffer of the most recent critical section events, for post-mortem debugging.
+//!
+//! [`Traced<I, C>`] wraps a critical section implementation `I`, recording each enter and exit in
+//! a fixed-size static buffer, timestamped with the clock `C`. Once [`LEN`] events are recorded,
+//! each new event overwrites the oldest one.
+//!
+//! The buffer is written while holding the critical section, but reading it never enters it, so
+//! it can be dumped from a panic handler or a fault handler, even if the critical section is held
+//! or its implementation is broken. Events being overwritten while they're read are skipped. The
+//! buffer is also a plain static, `critical_section::trace_buffer::BUFFER`, which can be inspected
+//! with a debugger.
+//!
+//! Only critical sections entered with [`Traced::with`] know their call site. The ones entered
+//! with other functions, such as [`crate::with`], are recorded without a location.
+//!
+//! This module requires the `trace-buffer` Cargo feature.
+//!
+//! # Example
+//!
+//! ```
+//! use critical_section::impls::Clock;
+//! use critical_section::trace_buffer::{self, Traced};
+//!
+//! struct MyCriticalSection;
+//! unsafe impl critical_section::Impl for MyCriticalSection {
+//!     unsafe fn acquire() -> critical_section::RawRestoreState {
+//!         // ...
+//! #       Default::default()
+//!     }
+//!
+//!     unsafe fn release(restore_state: critical_section::RawRestoreState) {
+//!         // ...
+//!     }
+//! }
+//!
+//! struct CycleCounter;
+//! impl Clock for CycleCounter {
+//!     fn now() -> u64 {
+//!         // Read the cycle counter.
+//! #       0
+//!     }
+//! }
+//!
+//! type Cs = Traced<MyCriticalSection, CycleCounter>;
+//! # #[cfg(not(feature = "std"))] // needed for `cargo test --features std`
+//! critical_section::set_impl!(Cs);
+//!
+//! # struct Uart;
+//! # impl core::fmt::Write for Uart {
+//! #     fn write_str(&mut self, _: &str) -> core::fmt::Result { Ok(()) }
+//! # }
+//! // In the panic handler:
+//! trace_buffer::dump(&mut Uart).ok();
+//! ```
+
+// `RawRestoreState` is `()` without any `restore-state-*` feature.
+#![allow(clippy::let_unit_value, clippy::unit_arg)]
+
+use core::fmt;
+use core::marker::PhantomData;
+use core::panic::Location;
+use core::ptr;
+use core::sync::atomic::{fence, AtomicPtr, AtomicUsize, Ordering};
+
+use crate::impls::wrapper::{forward_impl, with_location, Frame, Frames, Located};
+use crate::impls::Clock;
+use crate::{CriticalSection, Impl};
+
+/// Number of events kept in the buffer.
+pub const LEN: usize = 64;
+
+// Critical sections nested deeper than this have no location in their exit event.
+const MAX_DEPTH: usize = 16;
+
+// Timestamps are split in `usize` words, since not all targets have 64-bit atomics.
+const TIMESTAMP_WORDS: usize = 8 / core::mem::size_of::<usize>();
+
+/// Kind of an [`Event`].
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum EventKind {
+    /// The critical section was entered.
+    Enter,
+    /// The critical section was exited.
+    Exit,
+}
+
+/// Enter or exit of the critical section, recorded by [`Traced`].
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Event {
+    /// Whether the critical section was entered or exited.
+    pub kind: EventKind,
+    /// Time of the event, in [`Clock`] ticks.
+    pub timestamp: u64,
+    /// Nesting depth of the critical section entered or exited, `1` for the outermost one.
+    pub depth: usize,
+    /// Location of the call to [`Traced::with`] entering the critical section, or `None` for
+    /// critical sections entered with other functions.
+    pub location: Option<&'static Location<'static>>,
+}
+
+impl fmt::Display for Event {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let kind = match self.kind {
+            EventKind::Enter => "enter",
+            EventKind::Exit => "exit",
+        };
+        write!(f, "[{}] {} (depth {})", self.timestamp, kind, self.depth)?;
+        if let Some(location) = self.location {
+            write!(f, " at {}", location)?;
+        }
+        Ok(())
+    }
+}
+
+// A slot of the buffer, protected by a sequence lock so readers never see a partially written
+// event. Writers are serialized by the critical section.
+struct Slot {
+    // Odd while the slot is being written.
+    seq: AtomicUsize,
+    // Index of the event in the slot, to detect slots overwritten before being read.
+    index: AtomicUsize,
+    timestamp: [AtomicUsize; TIMESTAMP_WORDS],
+    location: AtomicPtr<Location<'static>>,
+    // Depth shifted left by one, with the lowest bit set for exit events.
+    depth_kind: AtomicUsize,
+}
+
+#[allow(clippy::declare_interior_mutable_const)]
+const EMPTY_WORD: AtomicUsize = AtomicUsize::new(0);
+#[allow(clippy::declare_interior_mutable_const)]
+const EMPTY_SLOT: Slot = Slot {
+    seq: AtomicUsize::new(0),
+    index: AtomicUsize::new(0),
+    timestamp: [EMPTY_WORD; TIMESTAMP_WORDS],
+    location: AtomicPtr::new(ptr::null_mut()),
+    depth_kind: AtomicUsize::new(0),
+};
+
+/// The buffer read by [`events`] and [`dump`].
+///
+/// It's only public to be found by debuggers: `head` is the number of events recorded so far,
+/// and event `i` is in `slots[i % LEN]`.
+#[doc(hidden)]
+pub struct Buffer {
+    head: AtomicUsize,
+    slots: [Slot; LEN],
+}
+
+#[doc(hidden)]
+pub static BUFFER: Buffer = Buffer {
+    head: AtomicUsize::new(0),
+    slots: [EMPTY_SLOT; LEN],
+};
+
+impl Slot {
+    /// Must be called while holding the critical section.
+    fn write(&self, index: usize, event: &Event) {
+        let seq = self.seq.load(Ordering::Relaxed);
+        self.seq.store(seq.wrapping_add(1), Ordering::Relaxed);
+        fence(Ordering::Release);
+
+        self.index.store(index, Ordering::Relaxed);
+        for (i, word) in self.timestamp.iter().enumerate() {
+            let shift = i as u32 * usize::BITS;
+            word.store((event.timestamp >> shift) as usize, Ordering::Relaxed);
+        }
+        let location = event.location.map_or(ptr::null(), |l| l as *const _);
+        self.location.store(location as *mut _, Ordering::Relaxed);
+        let exit = matches!(event.kind, EventKind::Exit) as usize;
+        self.depth_kind
+            .store(event.depth << 1 | exit, Ordering::Relaxed);
+
+        self.seq.store(seq.wrapping_add(2), Ordering::Release);
+    }
+
+    /// Returns the event in the slot, if it's event `index` and isn't being written.
+    fn read(&self, index: usize) -> Option<Event> {
+        let seq = self.seq.load(Ordering::Acquire);
+        if seq == 0 || seq % 2 == 1 {
+            return None;
+        }
+
+        let slot_index = self.index.load(Ordering::Relaxed);
+        let mut timestamp = 0;
+        for (i, word) in self.timestamp.iter().enumerate() {
+            let shift = i as u32 * usize::BITS;
+            timestamp |= (word.load(Ordering::Relaxed) as u64) << shift;
+        }
+        let location = self.location.load(Ordering::Relaxed);
+        let depth_kind = self.depth_kind.load(Ordering::Relaxed);
+
+        fence(Ordering::Acquire);
+        if self.seq.load(Ordering::Relaxed) != seq || slot_index != index {
+            return None;
+        }
+        Some(Event {
+            kind: if depth_kind & 1 == 0 {
+                EventKind::Enter
+            } else {
+                EventKind::Exit
+            },
+            timestamp,
+            depth: depth_kind >> 1,
+            // SAFETY: only `&'static Location` are stored.
+            location: unsafe { location.as_ref() },
+        })
+    }
+}
+
+/// Iterator over the recorded events, returned by [`events`].
+#[derive(Clone, Debug)]
+pub struct Events {
+    next: usize,
+    end: usize,
+}
+
+impl Iterator for Events {
+    type Item = Event;
+
+    fn next(&mut self) -> Option<Event> {
+        while self.next != self.end {
+            let index = self.next;
+            self.next = self.next.wrapping_add(1);
+            if let Some(event) = BUFFER.slots[index % LEN].read(index) {
+                return Some(event);
+            }
+        }
+        None
+    }
+}
+
+/// Returns the recorded events, oldest first.
+///
+/// This never enters the critical section. Events overwritten or being written while iterating
+/// are skipped.
+pub fn events() -> Events {
+    let end = BUFFER.head.load(Ordering::Acquire);
+    Events {
+        // `LEN` divides `usize::MAX + 1`, so this stays correct when the head wraps around.
+        next: end.wrapping_sub(LEN),
+        end,
+    }
+}
+
+/// Writes the recorded events to `w`, one per line, oldest first.
+///
+/// This never enters the critical section, so it can be called from a panic handler.
+pub fn dump<W: fmt::Write>(w: &mut W) -> fmt::Result {
+    for event in events() {
+        writeln!(w, "{}", event)?;
+    }
+    Ok(())
+}
+
+/// Critical section implementation wrapper recording each enter and exit in the trace buffer.
+///
+/// See the [module documentation](self) for details.
+///
+/// Writes to the buffer are serialized by the critical section of `I`, so only the registered
+/// implementation should be traced.
+pub struct Traced<I, C> {
+    _marker: PhantomData<(I, C)>,
+}
+
+// Index of the enter event of each acquired critical section.
+static FRAMES: Frames<usize, MAX_DEPTH> = Frames::new(
+    [Frame {
+        location: None,
+        data: 0,
+    }; MAX_DEPTH],
+);
+
+/// Must be called while holding the critical section.
+fn record(event: &Event) -> usize {
+    let index = BUFFER.head.load(Ordering::Relaxed);
+    BUFFER.slots[index % LEN].write(index, event);
+    BUFFER.head.store(index.wrapping_add(1), Ordering::Release);
+    index
+}
+
+impl<I: Impl, C: Clock> Traced<I, C> {
+    /// Execute closure `f` in a critical section, recording the call site in the trace buffer.
+    ///
+    /// This is the same as [`crate::with`], except that the enter and exit events are recorded
+    /// with the location of the caller of this function.
+    ///
+    /// # Panics
+    ///
+    /// This function panics if the given closure `f` panics. In this case
+    /// the critical section is released before unwinding.
+    #[inline]
+    #[track_caller]
+    pub fn with<R>(f: impl FnOnce(CriticalSection) -> R) -> R {
+        with_location::<Self, _, _>(f)
+    }
+
+    /// Must be called right after acquiring the critical section with `I`.
+    #[inline(always)]
+    unsafe fn push() {
+        let index = record(&Event {
+            kind: EventKind::Enter,
+            timestamp: C::now(),
+            depth: FRAMES.depth() + 1,
+            location: None,
+        });
+        FRAMES.push(index);
+    }
+
+    /// Must be called right before releasing the critical section with `I`.
+    #[inline(always)]
+    unsafe fn pop() {
+        let (depth, frame) = FRAMES.pop();
+        record(&Event {
+            kind: EventKind::Exit,
+            timestamp: C::now(),
+            depth,
+            location: frame.and_then(|frame| frame.location),
+        });
+    }
+}
+
+impl<I: Impl, C: Clock> Located for Traced<I, C> {
+    #[inline(always)]
+    unsafe fn set_location(location: &'static Location<'static>) {
+        // Rewrite the enter event, unless it was already overwritten.
+        if let Some(index) = FRAMES.set_location(location) {
+            let slot = &BUFFER.slots[index % LEN];
+            if let Some(mut event) = slot.read(index) {
+                event.location = Some(location);
+                slot.write(index, &event);
+            }
+        }
+    }
+}
+
+forward_impl!(Traced<I, C: Clock>);
+
+#[cfg(test)]
+mod tests {
+    extern crate std;
+
+    use core::panic::Location;
+    use core::sync::atomic::{AtomicU64, Ordering};
+    use std::string::String;
+    use std::vec::Vec;
+
+    use super::{dump, events, Event, EventKind, Traced, LEN};
+    use crate::impls::wrapper::{Located, NoopImpl};
+    use crate::impls::Clock;
+    use crate::Impl;
+
+    static NOW: AtomicU64 = AtomicU64::new(0);
+
+    // Ticks on every read, so timestamps give the order of the events.
+    struct CountingClock;
+
+    impl Clock for CountingClock {
+        fn now() -> u64 {
+            NOW.fetch_add(1, Ordering::Relaxed)
+        }
+    }
+
+    type MockTraced = Traced<NoopImpl, CountingClock>;
+
+    // The buffer can't be cleared, so this checks it from empty to wrapped around in one go.
+    #[test]
+    fn trace_buffer() {
+        assert_eq!(events().count(), 0);
+
+        let line = line!() + 4;
+        unsafe {
+            let outer = MockTraced::acquire();
+            let inner = MockTraced::acquire();
+            MockTraced::set_location(Location::caller());
+            MockTraced::release(inner);
+            MockTraced::release(outer);
+        }
+
+        let recorded: Vec<Event> = events().collect();
+        let summary: Vec<_> = recorded
+            .iter()
+            .map(|e| (e.kind, e.timestamp, e.depth, e.location.map(|l| l.line())))
+            .collect();
+        assert_eq!(
+            summary,
+            [
+                (EventKind::Enter, 0, 1, None),
+                (EventKind::Enter, 1, 2, Some(line)),
+                (EventKind::Exit, 2, 2, Some(line)),
+                (EventKind::Exit, 3, 1, None),
+            ]
+        );
+
+        let mut output = String::new();
+        dump(&mut output).unwrap();
+        assert_eq!(output.lines().count(), 4);
+        assert!(output.starts_with("[0] enter (depth 1)\n[1] enter (depth 2) at "));
+
+        // Only the most recent events are kept.
+        for _ in 0..LEN {
+            unsafe { MockTraced::release(MockTraced::acquire()) };
+        }
+        let recorded: Vec<Event> = events().collect();
+        assert_eq!(recorded.len(), LEN);
+        assert_eq!(recorded[0].timestamp, 4 + LEN as u64);
+        assert_eq!(recorded[LEN - 1].timestamp, 3 + 2 * LEN as u64);
+    }
+}
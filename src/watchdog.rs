@@ -0,0 +1,297 @@
+//! Hold-time watchdog for the `std` critical section implementation.
+//!
+//! A thread stalling while holding the critical section blocks every other thread trying to
+//! acquire it, which looks like a hang. Once [enabled](enable), the watchdog reports each time
+//! the critical section is held for longer than a threshold, with the thread holding it, how long
+//! it has held it, and where it was acquired.
+//!
+//! The location is where [`with`](crate::with) or [`acquire`](crate::acquire) was called.
+//! Backtraces give more context, but are expensive to capture on every acquisition, so they are
+//! disabled by default.
+//!
+//! This module requires the `std-watchdog` Cargo feature.
+//!
+//! # Example
+//!
+//! ```
+//! use std::time::Duration;
+//!
+//! use critical_section::watchdog;
+//!
+//! watchdog::enable(watchdog::Config {
+//!     capture_backtrace: true,
+//!     ..watchdog::Config::new(Duration::from_secs(1))
+//! });
+//! ```
+
+use std::backtrace::Backtrace;
+use std::fmt;
+use std::panic::Location;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex, MutexGuard, Once, PoisonError};
+use std::thread::{self, Thread, ThreadId};
+use std::time::{Duration, Instant};
+
+/// Configuration of the watchdog.
+#[derive(Clone, Copy)]
+pub struct Config {
+    /// Holding the critical section longer than this is reported.
+    pub threshold: Duration,
+    /// Whether to capture a backtrace each time the critical section is acquired, to include it
+    /// in reports.
+    pub capture_backtrace: bool,
+    /// Called with each report, from the watchdog thread.
+    ///
+    /// It must not enter the critical section, since it's typically still held by the stalled
+    /// thread.
+    pub handler: fn(&Stall),
+}
+
+impl Config {
+    /// Creates a configuration with the given threshold, without backtraces, printing reports to
+    /// the standard error.
+    pub fn new(threshold: Duration) -> Self {
+        Config {
+            threshold,
+            capture_backtrace: false,
+            handler: print_stall,
+        }
+    }
+}
+
+// Not derived, since older compilers lack `Debug` for higher-ranked function pointers such as
+// `fn(&Stall)`.
+impl fmt::Debug for Config {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Config")
+            .field("threshold", &self.threshold)
+            .field("capture_backtrace", &self.capture_backtrace)
+            .field("handler", &(self.handler as *const ()))
+            .finish()
+    }
+}
+
+fn print_stall(stall: &Stall) {
+    std::eprintln!("{}", stall);
+}
+
+/// Report of the critical section being held for longer than the [configured](Config)
+/// threshold.
+#[derive(Debug)]
+pub struct Stall {
+    thread: Thread,
+    held_for: Duration,
+    location: Option<&'static Location<'static>>,
+    backtrace: Option<Arc<Backtrace>>,
+}
+
+impl Stall {
+    /// Returns the name of the thread holding the critical section, if it has one.
+    pub fn thread_name(&self) -> Option<&str> {
+        self.thread.name()
+    }
+
+    /// Returns the ID of the thread holding the critical section.
+    pub fn thread_id(&self) -> ThreadId {
+        self.thread.id()
+    }
+
+    /// Returns how long the critical section had been held when it was reported.
+    pub fn held_for(&self) -> Duration {
+        self.held_for
+    }
+
+    /// Returns where the critical section was acquired, if known.
+    pub fn location(&self) -> Option<&'static Location<'static>> {
+        self.location
+    }
+
+    /// Returns the backtrace captured when the critical section was acquired, if
+    /// [enabled](Config::capture_backtrace).
+    pub fn backtrace(&self) -> Option<&Backtrace> {
+        self.backtrace.as_deref()
+    }
+}
+
+impl fmt::Display for Stall {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "critical section held for {:?} by thread '{}' ({:?})",
+            self.held_for,
+            self.thread.name().unwrap_or("<unnamed>"),
+            self.thread.id()
+        )?;
+        if let Some(location) = self.location {
+            write!(f, ", acquired at {}", location)?;
+        }
+        if let Some(backtrace) = &self.backtrace {
+            write!(f, "\nbacktrace at acquisition:\n{}", backtrace)?;
+        }
+        Ok(())
+    }
+}
+
+// Thread currently holding the critical section.
+struct Holder {
+    thread: Thread,
+    since: Instant,
+    location: Option<&'static Location<'static>>,
+    backtrace: Option<Arc<Backtrace>>,
+    reported: bool,
+}
+
+// Fast path for when the watchdog is disabled.
+static ENABLED: AtomicBool = AtomicBool::new(false);
+static CONFIG: Mutex<Option<Config>> = Mutex::new(None);
+// Not the critical section's own mutex, so the watchdog thread can inspect it while the critical
+// section is held.
+static HOLDER: Mutex<Option<Holder>> = Mutex::new(None);
+// Whether `HOLDER` is set, so releasing doesn't lock it when the watchdog was never enabled. Only
+// accessed while holding the critical section.
+static HOLDER_SET: AtomicBool = AtomicBool::new(false);
+
+fn lock<T>(mutex: &Mutex<T>) -> MutexGuard<'_, T> {
+    // Ignore poison, same as the critical section itself.
+    mutex.lock().unwrap_or_else(PoisonError::into_inner)
+}
+
+/// Enables the watchdog, or changes its configuration if it's already enabled.
+///
+/// This starts a background thread the first time it's called.
+pub fn enable(config: Config) {
+    static START: Once = Once::new();
+
+    *lock(&CONFIG) = Some(config);
+    ENABLED.store(true, Ordering::Relaxed);
+    START.call_once(|| {
+        thread::Builder::new()
+            .name("critical-section-watchdog".into())
+            .spawn(watch)
+            .expect("failed to spawn the critical section watchdog thread");
+    });
+}
+
+/// Disables the watchdog.
+///
+/// Critical sections acquired while the watchdog is disabled are never reported.
+pub fn disable() {
+    ENABLED.store(false, Ordering::Relaxed);
+    *lock(&CONFIG) = None;
+}
+
+fn watch() {
+    loop {
+        let config = match *lock(&CONFIG) {
+            Some(config) => config,
+            None => {
+                thread::park_timeout(Duration::from_millis(100));
+                continue;
+            }
+        };
+
+        let stall = lock(&HOLDER).as_mut().and_then(|holder| {
+            let held_for = holder.since.elapsed();
+            if holder.reported || held_for < config.threshold {
+                return None;
+            }
+            // Only report each acquisition once.
+            holder.reported = true;
+            Some(Stall {
+                thread: holder.thread.clone(),
+                held_for,
+                location: holder.location,
+                backtrace: holder.backtrace.clone(),
+            })
+        });
+        // Call the handler without holding `HOLDER`, which would block the critical section.
+        if let Some(stall) = stall {
+            (config.handler)(&stall);
+        }
+
+        thread::sleep((config.threshold / 4).max(Duration::from_millis(1)));
+    }
+}
+
+/// Must be called right after the current thread acquires the outermost critical section.
+pub(crate) fn on_acquire(location: Option<&'static Location<'static>>) {
+    if !ENABLED.load(Ordering::Relaxed) {
+        return;
+    }
+    let capture_backtrace = matches!(
+        *lock(&CONFIG),
+        Some(Config {
+            capture_backtrace: true,
+            ..
+        })
+    );
+
+    *lock(&HOLDER) = Some(Holder {
+        thread: thread::current(),
+        since: Instant::now(),
+        location,
+        backtrace: capture_backtrace.then(|| Arc::new(Backtrace::force_capture())),
+        reported: false,
+    });
+    HOLDER_SET.store(true, Ordering::Relaxed);
+}
+
+/// Must be called right before the current thread releases the outermost critical section.
+pub(crate) fn on_release() {
+    // Not checking `ENABLED`, the watchdog may have been disabled since `HOLDER` was set.
+    if HOLDER_SET.load(Ordering::Relaxed) {
+        HOLDER_SET.store(false, Ordering::Relaxed);
+        *lock(&HOLDER) = None;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Mutex;
+    use std::thread;
+    use std::time::Duration;
+    use std::vec::Vec;
+
+    use super::{enable, Config, Stall};
+    use crate as critical_section;
+
+    #[test]
+    fn watchdog() {
+        // Only stalls of the thread spawned below, other tests may hold the critical section.
+        static STALLS: Mutex<Vec<(Duration, u32, bool)>> = Mutex::new(Vec::new());
+
+        fn record(stall: &Stall) {
+            if stall.thread_name() == Some("stalled") {
+                let line = stall.location().map_or(0, |l| l.line());
+                let has_backtrace = stall.backtrace().is_some();
+                STALLS
+                    .lock()
+                    .unwrap()
+                    .push((stall.held_for(), line, has_backtrace));
+            }
+        }
+
+        enable(Config {
+            capture_backtrace: true,
+            handler: record,
+            ..Config::new(Duration::from_millis(100))
+        });
+
+        let line = line!() + 4;
+        thread::Builder::new()
+            .name("stalled".into())
+            .spawn(|| {
+                critical_section::with(|_| thread::sleep(Duration::from_millis(500)));
+            })
+            .unwrap()
+            .join()
+            .unwrap();
+
+        let stalls = STALLS.lock().unwrap();
+        assert_eq!(stalls.len(), 1);
+        let (held_for, stall_line, has_backtrace) = stalls[0];
+        assert!(held_for >= Duration::from_millis(100));
+        assert_eq!(stall_line, line);
+        assert!(has_backtrace);
+    }
+}